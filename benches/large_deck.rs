@@ -0,0 +1,31 @@
+//! Measures how long it takes to write a package with 50,000 notes
+//!
+//! Run with `cargo bench --bench large_deck`.
+
+use genanki_rs::{basic_model, Deck, Note, Package};
+use std::time::Instant;
+
+const NOTES: usize = 50_000;
+
+fn main() {
+    let model = basic_model();
+    let mut deck = Deck::new(1234, "Large Deck", "");
+    for i in 0..NOTES {
+        deck.add_note(
+            Note::new(
+                &model,
+                vec![format!("Question {}", i), format!("Answer {}", i)],
+            )
+            .unwrap(),
+        );
+    }
+    let mut package = Package::from_decks(vec![deck]);
+    let start = Instant::now();
+    let bytes = package.write_to_vec().unwrap();
+    println!(
+        "wrote {} notes ({} bytes) in {:?}",
+        NOTES,
+        bytes.len(),
+        start.elapsed()
+    );
+}
@@ -0,0 +1,43 @@
+//! Compares writing a package with 1,000 media files sequentially and in parallel
+//!
+//! Run with `cargo bench --bench parallel_media`.
+
+use genanki_rs::Package;
+use std::time::Instant;
+
+const MEDIA_FILES: usize = 1_000;
+const MEDIA_SIZE: usize = 64 * 1024;
+
+fn write(parallel: bool) {
+    let media = (0..MEDIA_FILES)
+        .map(|i| {
+            // Repetitive but not constant contents, so deflate has some work to do
+            let data = (0..MEDIA_SIZE)
+                .map(|j| ((i * 31 + j * 7) % 251) as u8 ^ (j / 97) as u8)
+                .collect::<Vec<_>>();
+            (format!("{}.bin", i), data)
+        })
+        .collect::<Vec<_>>();
+    let mut package = Package::new_with_bytes(
+        vec![],
+        media
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.clone()))
+            .collect(),
+    )
+    .parallel(parallel);
+    let start = Instant::now();
+    let bytes = package.write_to_vec().unwrap();
+    println!(
+        "{}: wrote {} media files ({} bytes) in {:?}",
+        if parallel { "parallel" } else { "sequential" },
+        MEDIA_FILES,
+        bytes.len(),
+        start.elapsed()
+    );
+}
+
+fn main() {
+    write(false);
+    write(true);
+}
@@ -53,7 +53,7 @@ INSERT INTO col VALUES(
                 0,
                 0
             ],
-            "usn": 0
+            "usn": -1
         }
     }',
     '{
@@ -99,7 +99,7 @@ INSERT INTO col VALUES(
                 "perDay": 100
             },
             "timer": 0,
-            "usn": 0
+            "usn": -1
         }
     }',
     '{}'
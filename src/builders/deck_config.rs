@@ -0,0 +1,118 @@
+use crate::db_entries::{
+    DeckConfigDbEntry, LapseConfigDbEntry, NewConfigDbEntry, RevConfigDbEntry,
+};
+
+/// Options group (scheduling configuration) to be fed into a `Deck`.
+///
+/// A `DeckConfig` can be created using the builder pattern.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Deck, DeckConfig};
+///
+/// let config = DeckConfig::new(1543729419, "Slow and steady")
+///     .new_per_day(5)
+///     .rev_per_day(50)
+///     .steps(vec![1.0, 10.0, 60.0]);
+/// let deck = Deck::new(1234, "Example Deck", "").config(config);
+/// ```
+///
+/// The builder has the following default values, which match Anki's default options group:
+/// * `new_per_day` - `20`
+/// * `rev_per_day` - `100`
+/// * `steps` - `[1, 10]`
+/// * `lapse_steps` - `[10]`
+#[derive(Clone)]
+pub struct DeckConfig {
+    id: i64,
+    name: String,
+    new_per_day: Option<i64>,
+    rev_per_day: Option<i64>,
+    steps: Option<Vec<f64>>,
+    lapse_steps: Option<Vec<f64>>,
+}
+
+impl DeckConfig {
+    /// Creates a new options group with a unique(!) `id` and a `name`
+    ///
+    /// The `id` `1` is used by Anki's default options group.
+    pub fn new(id: i64, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            new_per_day: None,
+            rev_per_day: None,
+            steps: None,
+            lapse_steps: None,
+        }
+    }
+
+    /// Sets the maximum number of new cards introduced per day
+    pub fn new_per_day(mut self, value: i64) -> Self {
+        self.new_per_day = Some(value);
+        self
+    }
+
+    /// Sets the maximum number of reviews shown per day
+    pub fn rev_per_day(mut self, value: i64) -> Self {
+        self.rev_per_day = Some(value);
+        self
+    }
+
+    /// Sets the learning steps for new cards, in minutes
+    pub fn steps(mut self, value: Vec<f64>) -> Self {
+        self.steps = Some(value);
+        self
+    }
+
+    /// Sets the relearning steps for lapsed cards, in minutes
+    pub fn lapse_steps(mut self, value: Vec<f64>) -> Self {
+        self.lapse_steps = Some(value);
+        self
+    }
+
+    pub(crate) fn id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl From<DeckConfig> for DeckConfigDbEntry {
+    fn from(config: DeckConfig) -> Self {
+        DeckConfigDbEntry {
+            autoplay: true,
+            id: config.id,
+            lapse: LapseConfigDbEntry {
+                delays: config.lapse_steps.unwrap_or_else(|| vec![10.0]),
+                leech_action: 0,
+                leech_fails: 8,
+                min_int: 1,
+                mult: 0.0,
+            },
+            max_taken: 60,
+            deck_config_db_entry_mod: 0,
+            name: config.name,
+            new: NewConfigDbEntry {
+                bury: true,
+                delays: config.steps.unwrap_or_else(|| vec![1.0, 10.0]),
+                initial_factor: 2500,
+                ints: vec![1, 4, 7],
+                order: 1,
+                per_day: config.new_per_day.unwrap_or(20),
+                separate: true,
+            },
+            replayq: true,
+            rev: RevConfigDbEntry {
+                bury: true,
+                ease4: 1.3,
+                fuzz: 0.05,
+                ivl_fct: 1.0,
+                max_ivl: 36500,
+                min_space: 1,
+                per_day: config.rev_per_day.unwrap_or(100),
+            },
+            timer: 0,
+            usn: -1,
+        }
+    }
+}
@@ -13,18 +13,41 @@ use crate::db_entries::Fld;
 /// let field2 = Field::new("field2").font("Comic Sans").size(15);
 /// ```
 ///
-/// The builder has the following default values:
+/// The builder has the following default values, which match the defaults of new fields in Anki:
 /// * `sticky` - `false`
 /// * `rtl` - `false`
-/// * `font` - `Liberation Sans`
+/// * `font` - `Arial`
 /// * `size` - `20`
+/// * `description` - empty
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Field {
     name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     sticky: Option<bool>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     rtl: Option<bool>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     font: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     size: Option<i64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    description: Option<String>,
 }
 
 impl Field {
@@ -36,6 +59,7 @@ impl Field {
             rtl: None,
             font: None,
             size: None,
+            description: None,
         }
     }
 
@@ -46,12 +70,16 @@ impl Field {
     }
 
     /// Sets whether the currently created `Field` is right-to-left or not
+    ///
+    /// Enable this for fields in e.g. Arabic or Hebrew, so that they are edited right-to-left.
     pub fn rtl(mut self, value: bool) -> Self {
         self.rtl = Some(value);
         self
     }
 
     /// Sets whether the currently created `Field` is sticky or not
+    ///
+    /// The content of sticky fields is kept when adding the next note in Anki.
     pub fn sticky(mut self, value: bool) -> Self {
         self.sticky = Some(value);
         self
@@ -62,18 +90,42 @@ impl Field {
         self.size = Some(value);
         self
     }
+
+    /// Sets the description of the currently created `Field`
+    ///
+    /// Newer Anki versions show it as help text in the editor, older versions ignore it.
+    pub fn description(mut self, value: &str) -> Self {
+        self.description = Some(value.to_string());
+        self
+    }
 }
 
-impl Into<Fld> for Field {
-    fn into(self) -> Fld {
+impl From<Field> for Fld {
+    fn from(field: Field) -> Self {
         Fld {
-            name: self.name.to_string(),
+            name: field.name,
             media: vec![],
-            sticky: self.sticky.unwrap_or(false),
-            rtl: self.rtl.unwrap_or(false),
+            sticky: field.sticky.unwrap_or(false),
+            rtl: field.rtl.unwrap_or(false),
             ord: 0,
-            font: self.font.unwrap_or("Liberation Sans".to_string()),
-            size: self.size.unwrap_or(20),
+            font: field.font.unwrap_or_else(|| "Arial".to_string()),
+            size: field.size.unwrap_or(20),
+            description: field.description.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Fld> for Field {
+    /// Only keeps the options which differ from the defaults, so serialized fields stay short
+    fn from(fld: Fld) -> Self {
+        Field {
+            name: fld.name,
+            sticky: Some(fld.sticky).filter(|&sticky| sticky),
+            rtl: Some(fld.rtl).filter(|&rtl| rtl),
+            font: Some(fld.font).filter(|font| font != "Arial"),
+            size: Some(fld.size).filter(|&size| size != 20),
+            description: Some(fld.description).filter(|description| !description.is_empty()),
         }
     }
 }
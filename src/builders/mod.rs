@@ -1,5 +1,7 @@
+mod deck_config;
 mod field;
 mod template;
 
+pub use deck_config::DeckConfig;
 pub use field::Field;
 pub use template::Template;
@@ -13,13 +13,39 @@ use crate::db_entries::Tmpl;
 /// ```
 ///
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Template {
     name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     qfmt: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     did: Option<usize>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     bafmt: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     afmt: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     bqfmt: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    ord: Option<i64>,
 }
 
 impl Template {
@@ -32,6 +58,7 @@ impl Template {
             bafmt: None,
             afmt: None,
             bqfmt: None,
+            ord: None,
         }
     }
 
@@ -48,6 +75,9 @@ impl Template {
     }
 
     /// Sets the browser answer format of the currently created `Template`
+    ///
+    /// It is shown in the card browser instead of the `afmt`. Defaults to an empty string, in
+    /// which case Anki uses the `afmt`.
     pub fn bafmt(mut self, bafmt: &str) -> Self {
         self.bafmt = Some(bafmt.to_string());
         self
@@ -59,23 +89,56 @@ impl Template {
         self
     }
 
-    /// Sets the browser question format of the currently created template
+    /// Sets the browser question format of the currently created `Template`
+    ///
+    /// It is shown in the card browser instead of the `qfmt`. Defaults to an empty string, in
+    /// which case Anki uses the `qfmt`.
     pub fn bqfmt(mut self, bqfmt: &str) -> Self {
         self.bqfmt = Some(bqfmt.to_string());
         self
     }
+
+    /// Sets the ordinal of the currently created `Template`
+    ///
+    /// By default the ordinal is the position of the template in its `Model`. Setting it explicitly
+    /// allows matching the ordinals of a model which already exists in a collection. The ordinals
+    /// of a model must be unique and each less than the number of templates, otherwise writing the
+    /// model fails with `Error::InvalidTemplateOrd`.
+    pub fn ord(mut self, ord: i64) -> Self {
+        self.ord = Some(ord);
+        self
+    }
+
+    pub(crate) fn get_ord(&self) -> Option<i64> {
+        self.ord
+    }
+
+    /// Converts a template of a model back, keeping only the options which differ from the
+    /// defaults, so serialized templates stay short
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_db_entry(tmpl: Tmpl, position: usize) -> Self {
+        Self {
+            name: tmpl.name,
+            qfmt: Some(tmpl.qfmt),
+            did: tmpl.did,
+            bafmt: Some(tmpl.bafmt).filter(|bafmt| !bafmt.is_empty()),
+            afmt: Some(tmpl.afmt),
+            bqfmt: Some(tmpl.bqfmt).filter(|bqfmt| !bqfmt.is_empty()),
+            ord: Some(tmpl.ord).filter(|&ord| ord != position as i64),
+        }
+    }
 }
 
-impl Into<Tmpl> for Template {
-    fn into(self) -> Tmpl {
+impl From<Template> for Tmpl {
+    fn from(template: Template) -> Self {
         Tmpl {
-            name: self.name,
-            qfmt: self.qfmt.unwrap_or("".to_string()),
-            did: self.did,
-            bafmt: self.bafmt.unwrap_or("".to_string()),
-            afmt: self.afmt.unwrap_or("".to_string()),
-            ord: 0,
-            bqfmt: self.bqfmt.unwrap_or("".to_string()),
+            name: template.name,
+            qfmt: template.qfmt.unwrap_or_default(),
+            did: template.did,
+            bafmt: template.bafmt.unwrap_or_default(),
+            afmt: template.afmt.unwrap_or_default(),
+            ord: template.ord.unwrap_or(0),
+            bqfmt: template.bqfmt.unwrap_or_default(),
         }
     }
 }
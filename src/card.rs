@@ -1,56 +1,299 @@
 use rusqlite::{params, Transaction};
-use std::ops::RangeFrom;
 
+use crate::util::IdGen;
 use crate::{error::database_error, Error};
 
+/// Colored flag of a card, shown in the browser and the reviewer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CardFlag {
+    #[default]
+    None,
+    Red,
+    Orange,
+    Green,
+    Blue,
+    Pink,
+    Turquoise,
+    Purple,
+}
+
+impl CardFlag {
+    /// The value of the flag in the lowest 3 bits of `cards.flags`
+    fn bits(self) -> i64 {
+        match self {
+            CardFlag::None => 0,
+            CardFlag::Red => 1,
+            CardFlag::Orange => 2,
+            CardFlag::Green => 3,
+            CardFlag::Blue => 4,
+            CardFlag::Pink => 5,
+            CardFlag::Turquoise => 6,
+            CardFlag::Purple => 7,
+        }
+    }
+}
+
+/// Type of a card, written to `cards.type`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CardType {
+    #[default]
+    New,
+    Learning,
+    Review,
+    Relearning,
+}
+
+impl CardType {
+    fn value(self) -> i64 {
+        match self {
+            CardType::New => 0,
+            CardType::Learning => 1,
+            CardType::Review => 2,
+            CardType::Relearning => 3,
+        }
+    }
+}
+
+/// Queue a card is shown from, written to `cards.queue`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CardQueue {
+    /// Buried by the scheduler, e.g. a sibling of a card reviewed today
+    SchedulerBuried,
+    /// Buried by the user
+    UserBuried,
+    Suspended,
+    #[default]
+    New,
+    /// Learning cards with a step of less than a day, `due` is a unix timestamp in seconds
+    Learning,
+    /// Review cards, `due` is the number of days since the collection was created
+    Review,
+    /// Learning cards with a step of a day or more, `due` is the number of days since the
+    /// collection was created
+    DayLearning,
+}
+
+impl CardQueue {
+    fn value(self) -> i64 {
+        match self {
+            CardQueue::SchedulerBuried => -3,
+            CardQueue::UserBuried => -2,
+            CardQueue::Suspended => -1,
+            CardQueue::New => 0,
+            CardQueue::Learning => 1,
+            CardQueue::Review => 2,
+            CardQueue::DayLearning => 3,
+        }
+    }
+}
+
+/// Scheduling state of a card which was already studied, see [`Note::card_scheduling`]
+///
+/// The default is a new card which was never studied.
+///
+/// Example:
+/// ```
+/// use genanki_rs::{CardQueue, CardType, SchedulingState};
+///
+/// let state = SchedulingState {
+///     card_type: CardType::Review,
+///     queue: CardQueue::Review,
+///     ivl: 21,
+///     factor: 2500,
+///     reps: 6,
+///     lapses: 1,
+///     ..SchedulingState::default()
+/// };
+/// ```
+///
+/// [`Note::card_scheduling`]: crate::Note::card_scheduling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SchedulingState {
+    /// Type of the card
+    pub card_type: CardType,
+    /// Queue the card is in
+    pub queue: CardQueue,
+    /// Interval in days for review cards, or negative seconds for learning cards
+    pub ivl: i64,
+    /// Ease factor in permille, e.g. `2500` for an ease of 250%
+    pub factor: i64,
+    /// Number of reviews
+    pub reps: i64,
+    /// Number of times the card went from review back to relearning
+    pub lapses: i64,
+    /// Due of the card in its original deck while it is in a filtered deck, or `0`
+    pub odue: i64,
+    /// Id of the original deck of the card while it is in a filtered deck, or `0`
+    ///
+    /// The original deck must be part of the package, otherwise writing it fails with
+    /// `Error::MissingDeck`.
+    pub odid: i64,
+}
+
+/// Kind of review in the review log, written to `revlog.type`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReviewKind {
+    Learning,
+    #[default]
+    Review,
+    Relearning,
+    /// Review of a card in a filtered deck which was not due
+    Filtered,
+    /// Card rescheduled by the user without a review
+    Manual,
+}
+
+impl ReviewKind {
+    fn value(self) -> i64 {
+        match self {
+            ReviewKind::Learning => 0,
+            ReviewKind::Review => 1,
+            ReviewKind::Relearning => 2,
+            ReviewKind::Filtered => 3,
+            ReviewKind::Manual => 4,
+        }
+    }
+}
+
+/// Entry of the review log of a card, see [`Note::add_review`]
+///
+/// Example:
+/// ```
+/// use genanki_rs::{ReviewKind, Revlog};
+///
+/// let review = Revlog {
+///     id: 1_600_000_000_000,
+///     ease: 3,
+///     ivl: 4,
+///     last_ivl: 1,
+///     factor: 2500,
+///     time: 6_500,
+///     review_kind: ReviewKind::Review,
+/// };
+/// ```
+///
+/// [`Note::add_review`]: crate::Note::add_review
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Revlog {
+    /// Time of the review in milliseconds since the unix epoch
+    pub id: i64,
+    /// Answer button pressed, from `1` (again) to `4` (easy), or `0` for manual rescheduling
+    pub ease: i64,
+    /// Interval after the review, in days or negative seconds like [`SchedulingState::ivl`]
+    pub ivl: i64,
+    /// Interval before the review
+    pub last_ivl: i64,
+    /// Ease factor in permille after the review
+    pub factor: i64,
+    /// Time spent on the review in milliseconds
+    pub time: i64,
+    /// Kind of the review
+    pub review_kind: ReviewKind,
+}
+
+impl Revlog {
+    fn write_to_db(
+        &self,
+        transaction: &Transaction,
+        card_id: i64,
+        id_gen: &mut IdGen,
+    ) -> Result<(), Error> {
+        let id = id_gen.unused_id(transaction, "revlog", self.id)?;
+        transaction
+            .prepare_cached("INSERT INTO revlog VALUES(?,?,?,?,?,?,?,?,?);")
+            .map_err(database_error)?
+            .execute(params![
+                id,                       // id
+                card_id,                  // cid
+                -1,                       // usn
+                self.ease,                // ease
+                self.ivl,                 // ivl
+                self.last_ivl,            // lastIvl
+                self.factor,              // factor
+                self.time,                // time
+                self.review_kind.value(), // type
+            ])
+            .map_err(database_error)?;
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct Card {
     pub ord: i64,
     pub suspend: bool,
+    pub due: i64,
+    pub deck_id: Option<i64>,
+    pub flag: CardFlag,
+    pub scheduling: SchedulingState,
+    pub reviews: Vec<Revlog>,
 }
 
 impl Card {
     pub fn new(ord: i64, suspend: bool) -> Self {
-        Self { ord, suspend }
+        Self {
+            ord,
+            suspend,
+            due: 0,
+            deck_id: None,
+            flag: CardFlag::None,
+            scheduling: SchedulingState::default(),
+            reviews: vec![],
+        }
     }
     #[allow(dead_code)]
     pub fn ord(&self) -> i64 {
         self.ord
     }
+    /// Writes the card of the note with `note_id`
+    ///
+    /// The card id is derived from `created` (in milliseconds) if it is set, otherwise it is taken
+    /// from `id_gen`.
     pub fn write_to_db(
         &self,
         transaction: &Transaction,
-        timestamp: f64,
+        modified: i64,
         deck_id: i64,
-        note_id: usize,
-        id_gen: &mut RangeFrom<usize>,
+        note_id: i64,
+        created: Option<i64>,
+        id_gen: &mut IdGen,
     ) -> Result<(), Error> {
-        let queue = if self.suspend { -1 } else { 0 };
+        let id = match created {
+            Some(created) => id_gen.unused_id(transaction, "cards", created)?,
+            None => id_gen.next_unused_id(transaction, "cards")?,
+        };
+        let queue = if self.suspend {
+            CardQueue::Suspended
+        } else {
+            self.scheduling.queue
+        };
         transaction
-            .execute(
-                "INSERT INTO cards VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);",
-                params![
-                    id_gen.next(),    // id
-                    note_id,          // nid
-                    deck_id,          // did
-                    self.ord,         // ord
-                    timestamp as i64, // mod
-                    -1,               // usn
-                    0,                // type (=0 for non-Cloze)
-                    queue,            // queue
-                    0,                // due
-                    0,                // ivl
-                    0,                // factor
-                    0,                // reps
-                    0,                // lapses
-                    0,                // left
-                    0,                // odue
-                    0,                // odid
-                    0,                // flags
-                    "",               // data
-                ],
-            )
+            .prepare_cached("INSERT INTO cards VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);")
+            .map_err(database_error)?
+            .execute(params![
+                id,                                // id
+                note_id,                           // nid
+                self.deck_id.unwrap_or(deck_id),   // did
+                self.ord,                          // ord
+                modified,                          // mod
+                -1,                                // usn
+                self.scheduling.card_type.value(), // type
+                queue.value(),                     // queue
+                self.due,                          // due
+                self.scheduling.ivl,               // ivl
+                self.scheduling.factor,            // factor
+                self.scheduling.reps,              // reps
+                self.scheduling.lapses,            // lapses
+                0,                                 // left
+                self.scheduling.odue,              // odue
+                self.scheduling.odid,              // odid
+                self.flag.bits(),                  // flags
+                "",                                // data
+            ])
             .map_err(database_error)?;
+        for review in &self.reviews {
+            review.write_to_db(transaction, id, id_gen)?;
+        }
         Ok(())
     }
 }
@@ -1,7 +1,8 @@
 use crate::deck::Deck;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct DeckDbEntry {
     pub collapsed: bool,
     pub conf: i64,
@@ -29,17 +30,78 @@ pub struct DeckDbEntry {
 
 impl<'a> From<Deck<'a>> for DeckDbEntry {
     fn from(deck: Deck) -> Self {
-        deck.into()
+        deck.to_deck_db_entry()
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeckConfigDbEntry {
+    pub autoplay: bool,
+    pub id: i64,
+    pub lapse: LapseConfigDbEntry,
+    #[serde(rename = "maxTaken")]
+    pub max_taken: i64,
+    #[serde(rename = "mod")]
+    pub deck_config_db_entry_mod: i64,
+    pub name: String,
+    pub new: NewConfigDbEntry,
+    pub replayq: bool,
+    pub rev: RevConfigDbEntry,
+    pub timer: i64,
+    pub usn: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LapseConfigDbEntry {
+    pub delays: Vec<f64>,
+    #[serde(rename = "leechAction")]
+    pub leech_action: i64,
+    #[serde(rename = "leechFails")]
+    pub leech_fails: i64,
+    #[serde(rename = "minInt")]
+    pub min_int: i64,
+    pub mult: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NewConfigDbEntry {
+    pub bury: bool,
+    pub delays: Vec<f64>,
+    #[serde(rename = "initialFactor")]
+    pub initial_factor: i64,
+    pub ints: Vec<i64>,
+    pub order: i64,
+    #[serde(rename = "perDay")]
+    pub per_day: i64,
+    pub separate: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevConfigDbEntry {
+    pub bury: bool,
+    pub ease4: f64,
+    pub fuzz: f64,
+    #[serde(rename = "ivlFct")]
+    pub ivl_fct: f64,
+    #[serde(rename = "maxIvl")]
+    pub max_ivl: i64,
+    #[serde(rename = "minSpace")]
+    pub min_space: i64,
+    #[serde(rename = "perDay")]
+    pub per_day: i64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ModelDbEntry {
+    #[serde(default)]
     pub vers: Vec<Option<serde_json::Value>>,
     pub name: String,
+    #[serde(default)]
     pub tags: Vec<Option<serde_json::Value>>,
+    #[serde(default)]
     pub did: i64,
     pub usn: i64,
+    #[serde(default)]
     pub req: Vec<(usize, String, Vec<usize>)>,
     pub flds: Vec<Fld>,
     pub sortf: i64,
@@ -50,21 +112,28 @@ pub struct ModelDbEntry {
     pub latex_post: String,
     #[serde(rename = "type")]
     pub model_db_entry_type: i64,
+    #[serde(deserialize_with = "string_or_int")]
     pub id: String,
     pub css: String,
     #[serde(rename = "latexPre")]
     pub latex_pre: String,
+    /// Keys written by newer Anki versions which are kept as they are
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Fld {
     pub name: String,
+    #[serde(default)]
     pub media: Vec<Option<serde_json::Value>>,
     pub sticky: bool,
     pub rtl: bool,
     pub ord: i64,
     pub font: String,
     pub size: i64,
+    #[serde(default)]
+    pub description: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -72,8 +141,24 @@ pub struct Tmpl {
     pub name: String,
     pub qfmt: String,
     pub did: Option<usize>,
+    #[serde(default)]
     pub bafmt: String,
     pub afmt: String,
     pub ord: i64,
+    #[serde(default)]
     pub bqfmt: String,
 }
+
+/// Model ids are written as strings, but newer Anki versions store them as numbers
+fn string_or_int<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrInt {
+        String(String),
+        Int(i64),
+    }
+    Ok(match StringOrInt::deserialize(deserializer)? {
+        StringOrInt::String(s) => s,
+        StringOrInt::Int(i) => i.to_string(),
+    })
+}
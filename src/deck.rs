@@ -1,12 +1,18 @@
 use super::Package;
-use crate::db_entries::{DeckDbEntry, ModelDbEntry};
+use crate::builders::DeckConfig;
+use crate::db_entries::{DeckConfigDbEntry, DeckDbEntry};
 use crate::error::{database_error, json_error};
-use crate::model::Model;
 use crate::note::Note;
+use crate::package::DEFAULT_DECK_ID;
+use crate::util::IdGen;
 use crate::Error;
 use rusqlite::{params, Transaction};
-use std::collections::HashMap;
-use std::ops::RangeFrom;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Separator between the levels of a deck hierarchy, e.g. `Spanish::Verbs::Irregular`
+const SUBDECK_SEPARATOR: &str = "::";
 
 /// A flashcard deck which can be written into an .apkg file.
 #[derive(Clone)]
@@ -15,20 +21,38 @@ pub struct Deck<'a> {
     name: String,
     description: String,
     notes: Vec<Note<'a>>,
-    models: HashMap<i64, Model>,
+    /// The note added with [`Deck::intro_note`], written before `notes`
+    intro_note: Option<Note<'a>>,
+    config: Option<DeckConfig>,
 }
 
 impl<'a> Deck<'a> {
     /// Creates a new deck with an `id`, `name` and `description`.
     ///
-    /// `id` should always be unique when creating multiple decks.
+    /// `id` should always be unique when creating multiple decks. The `description` is shown on
+    /// the deck overview screen in Anki and may contain HTML.
+    ///
+    /// Subdecks can be created by separating the levels of the `name` with `::`, e.g.
+    /// `"Spanish::Verbs::Irregular"`. Parent decks that are not part of the package are created
+    /// automatically when writing, with ids derived from their names.
     pub fn new(id: i64, name: &str, description: &str) -> Self {
         Self {
             id,
             name: name.to_string(),
             description: description.to_string(),
             notes: vec![],
-            models: HashMap::new(),
+            intro_note: None,
+            config: None,
+        }
+    }
+
+    /// Sets the options group (e.g. new cards per day) used by the deck.
+    ///
+    /// Without a config, the deck uses Anki's default options group.
+    pub fn config(self, config: DeckConfig) -> Self {
+        Self {
+            config: Some(config),
+            ..self
         }
     }
 
@@ -47,26 +71,166 @@ impl<'a> Deck<'a> {
         self.notes.push(note);
     }
 
-    fn add_model(&mut self, model: Model) {
-        self.models.insert(model.id, model);
+    /// Adds all `notes` (Flashcards) to the deck.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Deck, Error, Note, basic_model};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let model = basic_model();
+    /// let rows = [("Capital of France", "Paris"), ("Capital of Spain", "Madrid")];
+    /// let notes = rows
+    ///     .iter()
+    ///     .map(|&(question, answer)| Note::new(&model, vec![question, answer]))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    ///
+    /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
+    /// my_deck.add_notes(notes);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_notes(&mut self, notes: impl IntoIterator<Item = Note<'a>>) {
+        self.notes.extend(notes);
+    }
+
+    /// Adds a `note` whose cards are shown before all other new cards of the deck.
+    ///
+    /// This is useful for a "welcome" card with instructions, which learners should see first
+    /// after importing the deck. When writing, the `due` of all cards of the note is set to one
+    /// less than the lowest `due` of the other notes of the deck, but at most `0`, so it can be
+    /// negative. A `due` set on the note itself, e.g. with [`Note::due`], is replaced. Calling
+    /// this again replaces the previous intro note.
+    ///
+    /// The intro note is kept apart from the other notes, so it is not part of [`Deck::notes`].
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Deck, Note, basic_model};
+    ///
+    /// let model = basic_model();
+    /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
+    /// my_deck.add_note(Note::new(&model, vec!["Capital of France", "Paris"]).unwrap());
+    /// my_deck.intro_note(Note::new(&model, vec!["Welcome!", "Answer with the capital"]).unwrap());
+    /// ```
+    pub fn intro_note(&mut self, note: Note<'a>) {
+        self.intro_note = Some(note);
+    }
+
+    /// Removes and returns the note added with [`Deck::intro_note`], if there is one.
+    pub fn remove_intro_note(&mut self) -> Option<Note<'a>> {
+        self.intro_note.take()
+    }
+
+    /// Removes and returns the note at position `index` of the deck.
+    ///
+    /// `index` is the position in [`Deck::notes`], so the intro note is not counted. The
+    /// following notes are shifted down by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds, like [`Vec::remove`].
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Deck, Note, basic_model};
+    ///
+    /// let model = basic_model();
+    /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
+    /// my_deck.add_note(Note::new(&model, vec!["Capital of France", "Paris"]).unwrap());
+    /// my_deck.add_note(Note::new(&model, vec!["Capital of Spain", "Barcelona"]).unwrap());
+    /// my_deck.remove_note(my_deck.notes().len() - 1);
+    /// assert_eq!(my_deck.note_count(), 1);
+    /// ```
+    pub fn remove_note(&mut self, index: usize) -> Note<'a> {
+        self.notes.remove(index)
+    }
+
+    /// Removes all notes from the deck, including the intro note.
+    pub fn clear_notes(&mut self) {
+        self.notes.clear();
+        self.intro_note = None;
+    }
+
+    /// Returns the notes of the deck, in the order they were added, without the intro note.
+    pub fn notes(&self) -> &[Note<'a>] {
+        &self.notes
+    }
+
+    /// Returns the notes of the deck for editing them in place.
+    ///
+    /// Notes are builders, so replace a note to change it, e.g.
+    /// `*note = note.clone().with_tag("edited")`.
+    pub fn notes_mut(&mut self) -> &mut [Note<'a>] {
+        &mut self.notes
+    }
+
+    /// Returns the number of notes in the deck, including the intro note.
+    pub fn note_count(&self) -> usize {
+        self.notes.len() + usize::from(self.intro_note.is_some())
+    }
+
+    /// Returns `true` if no notes have been added to the deck.
+    pub fn is_empty(&self) -> bool {
+        self.note_count() == 0
+    }
+
+    /// Returns the id of the deck, e.g. to put cards of other decks' notes into this deck.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Changes the id of the deck.
+    ///
+    /// Cards which were put into the old id with [`Note::card_deck`] are not updated.
+    pub fn set_id(&mut self, id: i64) {
+        self.id = id;
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Moves the notes of `other` into this deck
+    ///
+    /// The intro note of `other` stays the intro note if this deck has none, otherwise it is
+    /// added as a regular note.
+    pub(super) fn append(&mut self, other: Deck<'a>) {
+        match (&self.intro_note, other.intro_note) {
+            (None, intro_note) => self.intro_note = intro_note,
+            (Some(_), Some(intro_note)) => self.notes.push(intro_note),
+            (Some(_), None) => {}
+        }
+        self.notes.extend(other.notes);
+    }
+
+    /// Returns all notes of the deck, with the intro note first
+    pub(super) fn all_notes(&self) -> impl Iterator<Item = &Note<'a>> {
+        self.intro_note.iter().chain(&self.notes)
+    }
+
+    /// Returns the ids of the models of this deck's notes
+    pub(super) fn model_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.all_notes().map(Note::model_id)
+    }
+
+    /// Returns the ids of other decks cards of this deck's notes are put into
+    pub(super) fn card_deck_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.all_notes().flat_map(Note::card_deck_ids)
+    }
+
+    /// Returns the ids of the default decks of this deck's notes' models
+    pub(super) fn model_default_deck_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.all_notes().filter_map(Note::model_default_deck_id)
     }
 
-    fn to_deck_db_entry(&self) -> DeckDbEntry {
+    pub(crate) fn to_deck_db_entry(&self) -> DeckDbEntry {
         DeckDbEntry {
-            collapsed: false,
-            conf: 1,
-            desc: self.description.clone(),
-            deck_db_entry_dyn: 0,
-            extend_new: 0,
-            extend_rev: 50,
-            id: self.id,
-            lrn_today: vec![163, 2],
-            deck_db_entry_mod: 1425278051,
-            name: self.name.clone(),
-            new_today: vec![163, 2],
-            rev_today: vec![163, 0],
-            time_today: vec![163, 23598],
-            usn: -1,
+            conf: self.config.as_ref().map_or(1, DeckConfig::id),
+            ..deck_db_entry(self.id, &self.name, &self.description)
         }
     }
 
@@ -80,14 +244,32 @@ impl<'a> Deck<'a> {
         &mut self,
         transaction: &Transaction,
         timestamp: f64,
-        id_gen: &mut RangeFrom<usize>,
+        id_gen: &mut IdGen,
+        strict: bool,
+        skipped_notes: &mut Vec<String>,
     ) -> Result<(), Error> {
         let decks_json_str: String = transaction
             .query_row("SELECT decks FROM col", [], |row| row.get(0))
             .map_err(database_error)?;
-        let mut decks: HashMap<i64, DeckDbEntry> =
+        let mut decks: BTreeMap<i64, DeckDbEntry> =
             serde_json::from_str(&decks_json_str).map_err(json_error)?;
-        decks.insert(self.id, self.to_deck_db_entry());
+        let db_entry = DeckDbEntry {
+            deck_db_entry_mod: timestamp as i64,
+            ..self.to_deck_db_entry()
+        };
+        // The only deck already in the collection is the `Default` deck, a deck with its id is
+        // merged into it so the collection keeps its daily counters
+        match decks.get_mut(&self.id) {
+            Some(existing) => {
+                existing.name = db_entry.name;
+                existing.desc = db_entry.desc;
+                existing.conf = db_entry.conf;
+                existing.deck_db_entry_mod = db_entry.deck_db_entry_mod;
+            }
+            None => {
+                decks.insert(self.id, db_entry);
+            }
+        }
         transaction
             .execute(
                 "UPDATE col SET decks = ?",
@@ -95,26 +277,88 @@ impl<'a> Deck<'a> {
             )
             .map_err(database_error)?;
 
-        let models_json_str: String = transaction
-            .query_row("SELECT models FROM col", [], |row| row.get(0))
-            .map_err(database_error)?;
-        let mut models: HashMap<i64, ModelDbEntry> =
-            serde_json::from_str(&models_json_str).map_err(json_error)?;
-        for note in self.notes.clone().iter() {
-            self.add_model(note.model());
+        if let Some(config) = &self.config {
+            let dconf_json_str: String = transaction
+                .query_row("SELECT dconf FROM col", [], |row| row.get(0))
+                .map_err(database_error)?;
+            let mut dconf: BTreeMap<i64, DeckConfigDbEntry> =
+                serde_json::from_str(&dconf_json_str).map_err(json_error)?;
+            dconf.insert(config.id(), config.clone().into());
+            transaction
+                .execute(
+                    "UPDATE col SET dconf = ?",
+                    params![serde_json::to_string(&dconf).map_err(json_error)?],
+                )
+                .map_err(database_error)?;
+        }
+
+        if let Some(intro_note) = &mut self.intro_note {
+            let min_due = self.notes.iter().flat_map(Note::card_dues).min();
+            intro_note.set_due(min_due.map_or(0, |due| (due - 1).min(0)));
         }
-        for (i, model) in &mut self.models {
-            models.insert(*i, model.to_model_db_entry(timestamp, self.id)?);
+        for note in self.all_notes() {
+            // Anki rejects notes without cards on import
+            if !note.has_cards() {
+                if strict {
+                    return Err(Error::NoCardsGenerated {
+                        guid: note.get_guid(),
+                    });
+                }
+                skipped_notes.push(note.get_guid());
+                continue;
+            }
+            note.write_to_db(transaction, timestamp, self.id, id_gen)?;
+        }
+        Ok(())
+    }
+
+    /// Adds entries for all parent decks of subdecks that are not yet in the collection, and for
+    /// the `Default` deck if it is missing
+    ///
+    /// Must be called after all decks have been written, so that parent decks which are part of
+    /// the package are reused instead of being created twice.
+    pub(super) fn write_parent_decks_to_db(
+        transaction: &Transaction,
+        timestamp: f64,
+    ) -> Result<(), Error> {
+        let decks_json_str: String = transaction
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .map_err(database_error)?;
+        let mut decks: BTreeMap<i64, DeckDbEntry> =
+            serde_json::from_str(&decks_json_str).map_err(json_error)?;
+        // Anki expects the `Default` deck in every collection
+        decks.entry(DEFAULT_DECK_ID).or_insert_with(|| DeckDbEntry {
+            deck_db_entry_mod: timestamp as i64,
+            ..deck_db_entry(DEFAULT_DECK_ID, "Default", "")
+        });
+        let mut parent_names = decks
+            .values()
+            .flat_map(|deck| parent_deck_names(&deck.name))
+            .collect::<Vec<_>>();
+        parent_names.sort_unstable();
+        parent_names.dedup();
+        for parent_name in parent_names {
+            if decks.values().all(|deck| deck.name != parent_name) {
+                // The id is in the range of user chosen ids, so it may already belong to a deck
+                let mut id = deck_id_for_name(&parent_name);
+                while decks.contains_key(&id) {
+                    id += 1;
+                }
+                decks.insert(
+                    id,
+                    DeckDbEntry {
+                        deck_db_entry_mod: timestamp as i64,
+                        ..deck_db_entry(id, &parent_name, "")
+                    },
+                );
+            }
         }
         transaction
             .execute(
-                "UPDATE col SET models = ?",
-                [serde_json::to_string(&models).map_err(json_error)?],
+                "UPDATE col SET decks = ?",
+                params![serde_json::to_string(&decks).map_err(json_error)?],
             )
             .map_err(database_error)?;
-        for note in &mut self.notes {
-            note.write_to_db(&transaction, timestamp, self.id, id_gen)?;
-        }
         Ok(())
     }
 
@@ -141,10 +385,365 @@ impl<'a> Deck<'a> {
     /// let model = basic_model();
     /// my_deck.add_note(Note::new(&model, vec!["What is the capital of France.unwrap()", "Paris"]).unwrap());
     ///
-    /// Package::new(vec![my_deck], vec![]).unwrap().write_to_file("output.apkg").unwrap();
+    /// Package::from_decks(vec![my_deck]).write_to_file("output.apkg").unwrap();
     /// ```
-    pub fn write_to_file(&self, file: &str) -> Result<(), Error> {
-        Package::new(vec![self.clone()], vec![])?.write_to_file(file)?;
+    pub fn write_to_file(&self, file: impl AsRef<Path>) -> Result<(), Error> {
+        Package::from_decks(vec![self.clone()]).write_to_file(file)?;
         Ok(())
     }
 }
+
+fn deck_db_entry(id: i64, name: &str, description: &str) -> DeckDbEntry {
+    DeckDbEntry {
+        collapsed: false,
+        conf: 1,
+        desc: description.to_string(),
+        deck_db_entry_dyn: 0,
+        extend_new: 0,
+        extend_rev: 50,
+        id,
+        lrn_today: vec![163, 2],
+        deck_db_entry_mod: 1425278051,
+        name: name.to_string(),
+        new_today: vec![163, 2],
+        rev_today: vec![163, 0],
+        time_today: vec![163, 23598],
+        usn: -1,
+    }
+}
+
+/// Returns the names of all ancestors of the deck `name`, e.g. `["A", "A::B"]` for `"A::B::C"`
+fn parent_deck_names(name: &str) -> Vec<String> {
+    let levels = name.split(SUBDECK_SEPARATOR).collect::<Vec<_>>();
+    (1..levels.len())
+        .map(|depth| levels[..depth].join(SUBDECK_SEPARATOR))
+        .collect()
+}
+
+/// Derives a stable deck id from the deck `name`, in the same range genanki uses for random ids
+fn deck_id_for_name(name: &str) -> i64 {
+    let digest = Sha1::digest(name.as_bytes());
+    let mut hash_bytes = [0; 8];
+    hash_bytes.copy_from_slice(&digest[..8]);
+    (1 << 30) + (u64::from_be_bytes(hash_bytes) % (1 << 30)) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_entries::ModelDbEntry;
+    use crate::package::tests::write_and_open_collection;
+    use crate::{basic_and_reversed_card_model, basic_model, basic_type_in_the_answer_model};
+
+    fn read_decks(package: &mut Package) -> BTreeMap<i64, DeckDbEntry> {
+        let (conn, _db_file) = write_and_open_collection(package);
+        let decks_json: String = conn
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .unwrap();
+        serde_json::from_str(&decks_json).unwrap()
+    }
+
+    #[test]
+    fn deck_with_default_id() {
+        let model = basic_model();
+        let mut deck = Deck::new(DEFAULT_DECK_ID, "My Deck", "Replaces the default deck");
+        deck.add_note(Note::new(&model, vec!["a", "b"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let decks_json: String = conn
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .unwrap();
+        let decks: BTreeMap<i64, DeckDbEntry> = serde_json::from_str(&decks_json).unwrap();
+        assert_eq!(decks.len(), 1);
+        assert_eq!(decks[&DEFAULT_DECK_ID].name, "My Deck");
+        assert_eq!(decks[&DEFAULT_DECK_ID].desc, "Replaces the default deck");
+        assert_eq!(decks[&DEFAULT_DECK_ID].extend_new, 10);
+        let card_deck_id: i64 = conn
+            .query_row("SELECT did FROM cards", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(card_deck_id, DEFAULT_DECK_ID);
+
+        let deck = Deck::new(1234, "Example Deck", "");
+        let decks = read_decks(&mut Package::new(vec![deck], vec![]).unwrap());
+        assert_eq!(decks.len(), 2);
+        assert_eq!(decks[&DEFAULT_DECK_ID].name, "Default");
+    }
+
+    #[test]
+    fn description_written_to_db() {
+        let description =
+            r#"Deck with <b>HTML</b> &amp; a <a href="https://apps.ankiweb.net">link</a>"#;
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", description);
+        deck.add_note(Note::new(&model, vec!["a", "b"]).unwrap());
+        let decks = read_decks(&mut Package::new(vec![deck], vec![]).unwrap());
+        assert_eq!(decks[&1234].desc, description);
+        assert_eq!(decks[&1234].name, "Example Deck");
+    }
+
+    #[test]
+    fn add_notes_appends_in_order() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        deck.add_notes(
+            ["2", "3"]
+                .iter()
+                .map(|question| Note::new(&model, vec![*question, "more"]).unwrap()),
+        );
+        let (conn, _db_file) =
+            write_and_open_collection(&mut Package::new(vec![deck], vec![]).unwrap());
+        let questions = conn
+            .prepare("SELECT flds FROM notes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(|flds| flds.unwrap().split('\x1f').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(questions, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn note_count() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        assert!(deck.is_empty());
+        assert_eq!(deck.note_count(), 0);
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        deck.add_note(Note::new(&model, vec!["2", "two"]).unwrap());
+        assert!(!deck.is_empty());
+        assert_eq!(deck.note_count(), 2);
+    }
+
+    #[test]
+    fn remove_and_clear_notes() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_notes(
+            (1..=3).map(|i| Note::new(&model, vec![i.to_string(), "a".into()]).unwrap()),
+        );
+        let removed = deck.remove_note(1);
+        assert_eq!(removed.fields()[0], "2");
+        let questions = |deck: &Deck| {
+            deck.notes()
+                .iter()
+                .map(|note| note.fields()[0].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(questions(&deck), ["1", "3"]);
+
+        for note in deck.notes_mut() {
+            *note = note.clone().with_tag("edited");
+        }
+        let (conn, _db_file) =
+            write_and_open_collection(&mut Package::from_decks(vec![deck.clone()]));
+        let tags = conn
+            .prepare("SELECT tags FROM notes")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(tags, [" edited ", " edited "]);
+
+        deck.intro_note(Note::new(&model, vec!["Welcome", ""]).unwrap());
+        deck.intro_note(Note::new(&model, vec!["Welcome again", ""]).unwrap());
+        assert_eq!(deck.note_count(), 3);
+        // Reordering or removing the other notes does not touch the intro note
+        deck.notes_mut().swap(0, 1);
+        assert_eq!(questions(&deck), ["3", "1"]);
+        assert_eq!(deck.remove_note(0).fields()[0], "3");
+        assert_eq!(
+            deck.remove_intro_note().unwrap().fields()[0],
+            "Welcome again"
+        );
+        assert!(deck.remove_intro_note().is_none());
+        assert_eq!(questions(&deck), ["1"]);
+        deck.intro_note(Note::new(&model, vec!["Welcome", ""]).unwrap());
+        deck.clear_notes();
+        assert!(deck.is_empty());
+        deck.intro_note(Note::new(&model, vec!["Welcome", ""]).unwrap());
+        assert_eq!(deck.note_count(), 1);
+    }
+
+    #[test]
+    fn intro_note_is_due_first() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        deck.intro_note(Note::new(&model, vec!["Old intro", ""]).unwrap());
+        deck.intro_note(Note::new(&model, vec!["Welcome", ""]).unwrap().due(5));
+        deck.add_note(Note::new(&model, vec!["2", "two"]).unwrap().due(3));
+        assert_eq!(deck.note_count(), 3);
+        let (conn, _db_file) =
+            write_and_open_collection(&mut Package::new(vec![deck], vec![]).unwrap());
+        let first: String = conn
+            .query_row(
+                "SELECT notes.flds FROM cards JOIN notes ON cards.nid = notes.id \
+                 ORDER BY cards.due LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(first, "Welcome\x1f");
+        let min_due: i64 = conn
+            .query_row("SELECT MIN(due) FROM cards", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(min_due, -1);
+    }
+
+    #[test]
+    fn intro_note_kept_when_merging() {
+        let model = basic_model();
+        let intro_model = basic_and_reversed_card_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package = Package::from_decks(vec![deck]);
+        let mut other_deck = Deck::new(1234, "Example Deck", "");
+        other_deck.intro_note(Note::new(&intro_model, vec!["Welcome", ""]).unwrap());
+        package
+            .merge(Package::from_decks(vec![other_deck]))
+            .unwrap();
+        assert_eq!(package.total_notes(), 2);
+
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let (first, due): (String, i64) = conn
+            .query_row(
+                "SELECT notes.flds, cards.due FROM cards JOIN notes ON cards.nid = notes.id \
+                 ORDER BY cards.due LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((first.as_str(), due), ("Welcome\x1f", -1));
+        let models_json: String = conn
+            .query_row("SELECT models FROM col", [], |row| row.get(0))
+            .unwrap();
+        let models: BTreeMap<i64, ModelDbEntry> = serde_json::from_str(&models_json).unwrap();
+        assert!(models.contains_key(&intro_model.id));
+    }
+
+    #[test]
+    fn multiple_models_in_one_deck() {
+        let basic = basic_model();
+        let reversed = basic_and_reversed_card_model();
+        let typed = basic_type_in_the_answer_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&basic, vec!["1", "one"]).unwrap());
+        deck.add_note(Note::new(&reversed, vec!["2", "two"]).unwrap());
+        deck.add_note(Note::new(&typed, vec!["3", "three"]).unwrap());
+        deck.add_note(Note::new(&basic, vec!["4", "four"]).unwrap());
+        let (conn, _db_file) =
+            write_and_open_collection(&mut Package::new(vec![deck], vec![]).unwrap());
+        let models_json: String = conn
+            .query_row("SELECT models FROM col", [], |row| row.get(0))
+            .unwrap();
+        let models: BTreeMap<i64, ModelDbEntry> = serde_json::from_str(&models_json).unwrap();
+        assert_eq!(
+            models.keys().copied().collect::<Vec<_>>(),
+            vec![typed.id, reversed.id, basic.id]
+        );
+        let cards_per_model = conn
+            .prepare(
+                "SELECT notes.mid, COUNT(*) FROM cards JOIN notes ON cards.nid = notes.id \
+                 GROUP BY notes.mid ORDER BY notes.mid",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            cards_per_model,
+            vec![(typed.id, 1), (reversed.id, 2), (basic.id, 2)]
+        );
+    }
+
+    #[test]
+    fn set_id() {
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        assert_eq!(deck.id(), 1234);
+        deck.set_id(deck_id_for_name("Example Deck"));
+        let id = deck.id();
+        let decks = read_decks(&mut Package::new(vec![deck], vec![]).unwrap());
+        assert_eq!(decks[&id].name, "Example Deck");
+        assert!(!decks.contains_key(&1234));
+    }
+
+    #[test]
+    fn parent_deck_names_of_subdeck() {
+        assert_eq!(parent_deck_names("A::B::C"), vec!["A", "A::B"]);
+        assert!(parent_deck_names("A").is_empty());
+    }
+
+    #[test]
+    fn subdeck_parents_created() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Spanish::Verbs::Irregular", "");
+        deck.add_note(Note::new(&model, vec!["ser", "to be"]).unwrap());
+        let decks = read_decks(&mut Package::new(vec![deck], vec![]).unwrap());
+        let mut names = decks
+            .values()
+            .map(|deck| deck.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec![
+                "Default",
+                "Spanish",
+                "Spanish::Verbs",
+                "Spanish::Verbs::Irregular"
+            ]
+        );
+        assert!(decks.contains_key(&deck_id_for_name("Spanish")));
+        assert!(decks.contains_key(&deck_id_for_name("Spanish::Verbs")));
+    }
+
+    #[test]
+    fn parent_deck_id_does_not_replace_deck() {
+        let model = basic_model();
+        let taken_id = deck_id_for_name("Spanish");
+        let mut deck = Deck::new(taken_id, "Vocabulary", "");
+        deck.add_note(Note::new(&model, vec!["ser", "to be"]).unwrap());
+        let subdeck = Deck::new(1234, "Spanish::Verbs", "");
+        let decks = read_decks(&mut Package::new(vec![deck, subdeck], vec![]).unwrap());
+        assert_eq!(decks[&taken_id].name, "Vocabulary");
+        assert_eq!(decks[&(taken_id + 1)].name, "Spanish");
+    }
+
+    #[test]
+    fn subdeck_reuses_parent_in_package() {
+        let child = Deck::new(1234, "Spanish::Verbs", "");
+        let parent = Deck::new(5678, "Spanish", "My Spanish deck");
+        let decks = read_decks(&mut Package::new(vec![child, parent], vec![]).unwrap());
+        assert_eq!(decks.len(), 3);
+        assert_eq!(decks[&5678].desc, "My Spanish deck");
+    }
+
+    #[test]
+    fn config_written_to_db() {
+        let config = DeckConfig::new(5678, "Slow")
+            .new_per_day(5)
+            .rev_per_day(50)
+            .steps(vec![1.0, 30.0]);
+        let deck = Deck::new(1234, "Example Deck", "").config(config);
+        let (conn, _db_file) =
+            write_and_open_collection(&mut Package::new(vec![deck], vec![]).unwrap());
+        let dconf_json: String = conn
+            .query_row("SELECT dconf FROM col", [], |row| row.get(0))
+            .unwrap();
+        let dconf: BTreeMap<i64, DeckConfigDbEntry> = serde_json::from_str(&dconf_json).unwrap();
+        assert_eq!(dconf.len(), 2);
+        assert_eq!(dconf[&5678].name, "Slow");
+        assert_eq!(dconf[&5678].new.per_day, 5);
+        assert_eq!(dconf[&5678].rev.per_day, 50);
+        assert_eq!(dconf[&5678].new.delays, vec![1.0, 30.0]);
+
+        let decks_json: String = conn
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .unwrap();
+        let decks: BTreeMap<i64, DeckDbEntry> = serde_json::from_str(&decks_json).unwrap();
+        assert_eq!(decks[&1234].conf, 5678);
+    }
+}
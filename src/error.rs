@@ -1,4 +1,4 @@
-use std::{convert::Infallible, time::SystemTimeError};
+use std::{convert::Infallible, path::PathBuf, time::SystemTimeError};
 
 use zip::result::ZipError;
 
@@ -10,6 +10,10 @@ const fn _assert_sync<T: Sync>() {}
 const _: () = _assert_send::<Error>();
 const _: () = _assert_sync::<Error>();
 
+/// Errors returned by genanki-rs
+///
+/// The enum is `#[non_exhaustive]`, so new kinds of failures can be added without breaking
+/// client code. Match on the variants to distinguish e.g. IO errors from invalid input.
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
@@ -29,12 +33,86 @@ pub enum Error {
     /// client code.
     #[error(transparent)]
     JsonParser(Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates that a template does not reference any field on its front side
     #[error("Could not compute required fields for this template; please check the formatting of \"qfmt\": {0:?}")]
     TemplateFormat(Tmpl),
-    #[error("number of model field ({0}) does not match number of fields ({1})")]
-    ModelFieldCountMismatch(usize, usize),
+    /// Indicates that a note has a different number of fields than its model
+    #[error("number of fields of model {model:?} ({expected}) does not match number of note fields ({got})")]
+    FieldCountMismatch {
+        model: String,
+        expected: usize,
+        got: usize,
+    },
+    /// Indicates that the sort field index of a model is not the index of one of its fields
+    #[error("sort field index {index} of model {model:?} is out of range for {num_fields} fields")]
+    InvalidSortFieldIndex {
+        model: String,
+        index: i64,
+        num_fields: usize,
+    },
+    /// Indicates that the ordinals of a model's templates are not `0` to `n - 1` for `n` templates
+    #[error("template ordinal {ord} of model {model:?} is duplicated or out of range")]
+    InvalidTemplateOrd { model: String, ord: i64 },
+    /// Indicates that a template references a field which is not part of its model, see
+    /// [`Model::validate`]
+    ///
+    /// [`Model::validate`]: crate::Model::validate
+    #[error("template {template:?} references unknown field {field:?}")]
+    UnknownFieldReference { template: String, field: String },
+    /// Indicates that a tag of a note contains whitespace
     #[error("One of the tags contains whitespace, this is not allowed!")]
     TagContainsWhitespace,
+    /// Indicates that a note references a model which is not part of the package
+    #[error("note references model {0} which is not part of the package")]
+    MissingModel(i64),
+    /// Indicates that notes of a package use two different models with the same id
+    #[error("model id {0} is used by two different models")]
+    ModelIdConflict(i64),
+    /// Indicates that two decks, or a deck and a model, of a package have the same id
+    #[error("id {0} is used by more than one deck or model")]
+    DuplicateId(i64),
+    /// Indicates that a card is put into, or a model defaults to, a deck which is not part of the
+    /// package
+    #[error("deck {0} is used by a card or model but is not part of the package")]
+    MissingDeck(i64),
+    /// Indicates that a strict package contains no notes, see [`Package::strict`]
+    ///
+    /// [`Package::strict`]: crate::Package::strict
+    #[error("package contains no notes")]
+    EmptyPackage,
+    /// Indicates that a note of a strict package generates no cards because the front sides of
+    /// all its templates are empty, see [`Package::strict`]
+    ///
+    /// [`Package::strict`]: crate::Package::strict
+    #[error("note {guid:?} generates no cards")]
+    NoCardsGenerated { guid: String },
+    /// Indicates that two media files with the same filename but different contents are part of,
+    /// or merged into, a package, see [`Package::dedup_media`] and [`Package::merge`]
+    ///
+    /// [`Package::dedup_media`]: crate::Package::dedup_media
+    /// [`Package::merge`]: crate::Package::merge
+    #[error("media file {0:?} exists with different contents")]
+    MediaNameConflict(String),
+    /// Indicates that a media file path has no filename or the filename is not valid UTF-8
+    #[error("media file path {0:?} does not end in a valid UTF-8 filename")]
+    InvalidMediaPath(PathBuf),
+    /// Indicates that a media file path points to e.g. a directory instead of a regular file
+    #[error("media file path {0:?} is not a file")]
+    NotAFile(PathBuf),
+    /// Indicates that media files do not exist or cannot be read, see [`Package::verify_media`]
+    ///
+    /// [`Package::verify_media`]: crate::Package::verify_media
+    #[error("media files {0:?} do not exist or cannot be read")]
+    MissingMedia(Vec<PathBuf>),
+    /// Indicates an error reading the media file at `path`, e.g. because it was removed after
+    /// the media files were verified
+    #[error("could not read media file {path:?}")]
+    MediaRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Indicates an error reading or writing files
     #[error(transparent)]
     Io(#[from] std::io::Error),
     /// Indicates an error with the underlying template system
@@ -45,6 +123,7 @@ pub enum Error {
     /// client code.
     #[error(transparent)]
     Template(#[from] Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates that the system time is before the unix epoch
     #[error(transparent)]
     SystemTime(#[from] SystemTimeError),
     /// Indicates an error with zip file handling
@@ -55,6 +134,18 @@ pub enum Error {
     /// client code.
     #[error(transparent)]
     Zip(Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates an error reading a CSV file with `import::from_csv`
+    ///
+    /// Currently the argument is a `csv::Error`, but it is
+    /// cast to a Box<dyn std::error::Error> so that we can change
+    /// the underlying library in the future if needed without breaking
+    /// client code.
+    #[error(transparent)]
+    Csv(Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates that a column mapped to a field is missing from a line of a CSV file, or from
+    /// its header
+    #[error("CSV line {line} has no column {column}")]
+    MissingCsvColumn { line: u64, column: String },
 }
 
 impl From<Infallible> for Error {
@@ -65,11 +156,11 @@ impl From<Infallible> for Error {
 }
 
 pub(crate) fn database_error(e: rusqlite::Error) -> Error {
-    Error::Template(Box::new(e))
+    Error::Database(Box::new(e))
 }
 
 pub(crate) fn json_error(e: serde_json::Error) -> Error {
-    Error::Template(Box::new(e))
+    Error::JsonParser(Box::new(e))
 }
 
 pub(crate) fn template_error(e: ramhorns::Error) -> Error {
@@ -79,3 +170,24 @@ pub(crate) fn template_error(e: ramhorns::Error) -> Error {
 pub(crate) fn zip_error(e: ZipError) -> Error {
     Error::Zip(Box::new(e))
 }
+
+#[cfg(feature = "csv")]
+pub(crate) fn csv_error(e: csv::Error) -> Error {
+    Error::Csv(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers_map_to_matching_variants() {
+        assert!(matches!(
+            database_error(rusqlite::Error::InvalidQuery),
+            Error::Database(_)
+        ));
+        let e = serde_json::from_str::<i64>("no json").unwrap_err();
+        assert!(matches!(json_error(e), Error::JsonParser(_)));
+        assert!(matches!(zip_error(ZipError::FileNotFound), Error::Zip(_)));
+    }
+}
@@ -0,0 +1,256 @@
+//! Creating notes from other formats
+//!
+//! Requires the `csv` feature.
+
+use crate::error::csv_error;
+use crate::{Error, Model, Note};
+use csv::{ReaderBuilder, StringRecord};
+use std::io::Read;
+
+/// A column of a CSV file, either by its position or by its name in the header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// The column at this position, starting at `0`
+    Index(usize),
+    /// The column with this name in the header
+    Name(String),
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Self {
+        Column::Index(index)
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(name.to_string())
+    }
+}
+
+impl Column {
+    /// Returns the position of the column, looking up names in `headers`
+    fn position(&self, headers: Option<&StringRecord>) -> Option<usize> {
+        match self {
+            Column::Index(index) => Some(*index),
+            Column::Name(name) => headers?.iter().position(|header| header == name),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Column::Index(index) => index.to_string(),
+            Column::Name(name) => format!("{:?}", name),
+        }
+    }
+}
+
+/// Maps the columns of a CSV file to the fields of a model, see [`from_csv`]
+///
+/// A `CsvMapping` can be created using the builder pattern. It has the following default values:
+/// * `delimiter` - `b','`
+/// * `has_headers` - `true`, so the first line is not turned into a note
+/// * `tags` - no tag column
+/// * `blank_missing` - `false`, so lines which are too short to contain a column are an error
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::import::CsvMapping;
+///
+/// let by_name = CsvMapping::new(vec!["Question", "Answer"]).tags("Tags");
+/// let by_index = CsvMapping::new(vec![2, 0]).delimiter(b'\t').has_headers(false);
+/// ```
+#[derive(Clone, Debug)]
+pub struct CsvMapping {
+    fields: Vec<Column>,
+    delimiter: Option<u8>,
+    has_headers: Option<bool>,
+    tags: Option<Column>,
+    blank_missing: Option<bool>,
+}
+
+impl CsvMapping {
+    /// Creates a mapping which fills the fields of the model in order with `fields`, the columns
+    /// given by position or by name
+    pub fn new(fields: impl IntoIterator<Item = impl Into<Column>>) -> Self {
+        Self {
+            fields: fields.into_iter().map(Into::into).collect(),
+            delimiter: None,
+            has_headers: None,
+            tags: None,
+            blank_missing: None,
+        }
+    }
+
+    /// Sets the delimiter between columns, e.g. `b'\t'` for tab-separated files
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        Self {
+            delimiter: Some(delimiter),
+            ..self
+        }
+    }
+
+    /// Sets whether the first line of the file is a header with the names of the columns
+    ///
+    /// Without a header, columns can only be given by position.
+    pub fn has_headers(self, has_headers: bool) -> Self {
+        Self {
+            has_headers: Some(has_headers),
+            ..self
+        }
+    }
+
+    /// Sets the column with the tags of each note, separated by whitespace like in Anki
+    pub fn tags(self, column: impl Into<Column>) -> Self {
+        Self {
+            tags: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Sets whether a column missing from a line is read as an empty field instead of an error
+    ///
+    /// A column name which is not in the header is always an error.
+    pub fn blank_missing(self, blank_missing: bool) -> Self {
+        Self {
+            blank_missing: Some(blank_missing),
+            ..self
+        }
+    }
+}
+
+/// Creates a note of `model` for each line of the CSV file read from `reader`
+///
+/// The columns of each line are mapped to the fields of `model` with `mapping`. Fields are HTML,
+/// so `<`, `>` and `&` which should be shown literally must be encoded in the file. Quoted columns
+/// may contain the delimiter and line breaks.
+///
+/// Returns `Err` if the file is not valid CSV, if a column is missing (see
+/// [`CsvMapping::blank_missing`]), or if a note does not match `model`
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::basic_model;
+/// use genanki_rs::import::{from_csv, CsvMapping};
+///
+/// let model = basic_model();
+/// let csv = "Country,Capital,Tags
+/// Argentina,Buenos Aires,geo south-america
+/// \"Korea, South\",Seoul,geo
+/// ";
+/// let notes = from_csv(
+///     csv.as_bytes(),
+///     &model,
+///     &CsvMapping::new(vec!["Country", "Capital"]).tags("Tags"),
+/// )
+/// .unwrap();
+/// assert_eq!(notes[1].fields(), ["Korea, South", "Seoul"]);
+/// ```
+pub fn from_csv<'a, R: Read>(
+    reader: R,
+    model: &'a Model,
+    mapping: &CsvMapping,
+) -> Result<Vec<Note<'a>>, Error> {
+    let has_headers = mapping.has_headers.unwrap_or(true);
+    let mut csv_reader = ReaderBuilder::new()
+        .delimiter(mapping.delimiter.unwrap_or(b','))
+        .has_headers(has_headers)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = if has_headers {
+        Some(csv_reader.headers().map_err(csv_error)?.clone())
+    } else {
+        None
+    };
+    let position = |column: &Column| {
+        column
+            .position(headers.as_ref())
+            .ok_or_else(|| Error::MissingCsvColumn {
+                line: 1,
+                column: column.describe(),
+            })
+    };
+    let field_positions = mapping
+        .fields
+        .iter()
+        .map(|column| Ok((column, position(column)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let tags_position = match &mapping.tags {
+        Some(column) => Some((column, position(column)?)),
+        None => None,
+    };
+
+    let blank_missing = mapping.blank_missing.unwrap_or(false);
+    let mut notes = vec![];
+    for record in csv_reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let cell = |(column, position): (&Column, usize)| match record.get(position) {
+            Some(value) => Ok(value),
+            None if blank_missing => Ok(""),
+            None => Err(Error::MissingCsvColumn {
+                line,
+                column: column.describe(),
+            }),
+        };
+        let fields = field_positions
+            .iter()
+            .map(|&column| cell(column))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tags = match tags_position {
+            Some(column) => cell(column)?.split_whitespace().collect(),
+            None => vec![],
+        };
+        notes.push(Note::new_with_options(
+            model,
+            fields,
+            None,
+            Some(tags),
+            None,
+        )?);
+    }
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_model;
+
+    #[test]
+    fn quoted_columns_and_tags() {
+        let model = basic_model();
+        let csv = "Back;Front;Tags\n\"a; \"\"quoted\"\"\nanswer\";Question;x y\nb;c;\n";
+        let notes = from_csv(
+            csv.as_bytes(),
+            &model,
+            &CsvMapping::new(vec![Column::from("Front"), Column::Index(0)])
+                .delimiter(b';')
+                .tags("Tags"),
+        )
+        .unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].fields(), ["Question", "a; \"quoted\"\nanswer"]);
+        assert_eq!(notes[0].format_tags(), " x y ");
+        assert_eq!(notes[1].fields(), ["c", "b"]);
+        assert_eq!(notes[1].format_tags(), "  ");
+    }
+
+    #[test]
+    fn missing_columns() {
+        let model = basic_model();
+        let csv = "a,b\nc\n";
+        let mapping = CsvMapping::new(vec![0, 1]).has_headers(false);
+        assert!(matches!(
+            from_csv(csv.as_bytes(), &model, &mapping),
+            Err(Error::MissingCsvColumn { line: 2, column }) if column == "1"
+        ));
+        let notes = from_csv(csv.as_bytes(), &model, &mapping.blank_missing(true)).unwrap();
+        assert_eq!(notes[1].fields(), ["c", ""]);
+
+        assert!(matches!(
+            from_csv("Front,Back\n".as_bytes(), &model, &CsvMapping::new(vec!["Front", "Answer"])),
+            Err(Error::MissingCsvColumn { line: 1, column }) if column == "\"Answer\""
+        ));
+    }
+}
@@ -40,6 +40,11 @@
 //!
 //! You pass in a `Model`, discussed below, and a set of `fields` (encoded as HTML).
 //!
+//! `Note::new` borrows the `Model`, so the model has to outlive every `Deck` and `Package` the note
+//! is added to. If models and decks are kept in separate long-lived collections, put the model
+//! into an `Arc` and use [`Note::new_shared`] instead: the resulting notes own a reference to the
+//! model and are not bound to its lifetime.
+//!
 //! ### Models
 //! A `Model` defines the fields and cards for a type of `Note`. For example:
 //!
@@ -81,6 +86,9 @@
 //! You need to pass a model `id` and a model `name` so that Anki can keep track of your model. It's important that you use a unique model `id`
 //! for each `Model` you define.
 //!
+//! For Anki's stock note types, [`Model::basic`], [`Model::basic_and_reversed`] and [`Model::basic_type_in_answer`]
+//! create the model with your own `id`.
+//!
 //! ### Generating a Deck/Package
 //! To import your notes into Anki, you need to add them to a `Deck`:
 //!
@@ -109,6 +117,8 @@
 //! my_deck.write_to_file("output.apkg")?;
 //! ```
 //!
+//! To write several decks into one file, use [`Package::from_decks`] instead.
+//!
 //! You can then load `output.apkg` into Anki using File -> Import...
 //!
 //! ### Media Files
@@ -126,7 +136,12 @@
 //! }
 //! ```
 //!
-//! `media_files` should have the path (relative or absolute) to each file. To use them in notes, first add a field to your model, and reference that field in your template:
+//! `media_files` should have the path (relative or absolute) to each file. Media that only exists in memory can be added with
+//! [`Package::add_media_bytes`] (or [`Package::new_with_bytes`]) by giving the filename together with the data.
+//! To give a file on disk a different name in the package, create a [`MediaFile`] with that logical name and pass it to
+//! [`Package::new_with_media`].
+//!
+//! To use them in notes, first add a field to your model, and reference that field in your template:
 //!
 //! ```rust
 //! # use genanki_rs::{Template, Field, Model};
@@ -184,6 +199,9 @@
 //! the Note, `1` means the second, etc.
 //!
 
+// `Error::TemplateFormat` holds the template by value, boxing it would change the public API
+#![allow(clippy::result_large_err)]
+
 mod apkg_col;
 mod apkg_schema;
 mod builders;
@@ -192,18 +210,32 @@ mod card;
 mod db_entries;
 mod deck;
 mod error;
+#[cfg(feature = "csv")]
+pub mod import;
+mod media;
 mod model;
 mod note;
 mod package;
+mod reader;
+#[cfg(feature = "serde")]
+mod spec;
 mod util;
 
-pub use builders::{Field, Template};
+pub use builders::{DeckConfig, Field, Template};
 pub use builtin_models::*;
+pub use card::{CardFlag, CardQueue, CardType, ReviewKind, Revlog, SchedulingState};
 pub use deck::Deck;
 pub use error::Error;
-pub use model::{Model, ModelType};
-pub use note::Note;
-pub use package::Package;
+pub use media::{image, sound, MediaFile, MediaSource};
+pub use model::{Alignment, Model, ModelType, TextDirection, Warning};
+pub use note::{GuidStrategy, Note};
+pub use package::{Compression, Package, PackageBuilder, Progress, SchemaVersion};
+pub use reader::ImportedPackage;
+/// Re-export of the sqlite library used for [`Package::with_connection`]
+pub use rusqlite;
+#[cfg(feature = "serde")]
+pub use spec::{DeckSpec, NoteSpec};
+pub use util::{guid_for, strip_html_media};
 
 #[cfg(test)]
 mod tests {
@@ -603,11 +635,11 @@ def check_media(col):
         deck.add_note(note);
         std::fs::File::create("present.mp3")
             .unwrap()
-            .write(VALID_MP3)
+            .write_all(VALID_MP3)
             .unwrap();
         std::fs::File::create("present.jpg")
             .unwrap()
-            .write(VALID_JPG)
+            .write_all(VALID_JPG)
             .unwrap();
         Python::with_gil(|py| {
             let mut setup = TestSetup::new(&py);
@@ -647,11 +679,11 @@ def check_media(col):
         let present_jpg_path = tmp_dir.path().join("present.jpg");
         std::fs::File::create(present_mp3_path.clone())
             .unwrap()
-            .write(VALID_MP3)
+            .write_all(VALID_MP3)
             .unwrap();
         std::fs::File::create(present_jpg_path.clone())
             .unwrap()
-            .write(VALID_JPG)
+            .write_all(VALID_JPG)
             .unwrap();
         Python::with_gil(|py| {
             let mut setup = TestSetup::new(&py);
@@ -688,6 +720,23 @@ def check_media(col):
         });
     }
 
+    #[test]
+    #[serial]
+    fn type_in_the_answer_card() {
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            let mut deck = Deck::new(1104693946, "foodeck", "");
+            let model = basic_type_in_the_answer_model();
+            let note = Note::new(&model, vec!["a", "b"]).unwrap();
+            deck.add_note(note);
+            setup.import_package(Package::from_decks(vec![deck]), None);
+            assert!(setup.check_col(
+                "len(col.find_cards('')) == 1 and \
+                 '{{type:Back}}' in col.getNote(col.find_notes('')[0]).model()['tmpls'][0]['qfmt']"
+            ))
+        });
+    }
+
     #[test]
     #[serial]
     fn card_added_date_is_recent() {
@@ -0,0 +1,320 @@
+use sha1::{Digest, Sha1};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// A media file of a package
+///
+/// The `logical_name` is the filename Anki sees, so it is what fields should reference, e.g.
+/// `[sound:hello.mp3]`. It is independent of where the contents come from, so a file on disk at
+/// `tmp/abc123.mp3` can be added as `hello.mp3`.
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::{MediaFile, MediaSource};
+/// use std::path::PathBuf;
+///
+/// let on_disk = MediaFile::from("sounds/hello.mp3");
+/// assert_eq!(on_disk.logical_name, "hello.mp3");
+/// let renamed = MediaFile::new("hello.mp3", MediaSource::Path(PathBuf::from("tmp/abc123.mp3")));
+/// let in_memory = MediaFile::new("hello.mp3", MediaSource::Bytes(vec![0xff, 0xe3]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct MediaFile {
+    /// The filename Anki will see for this media file
+    pub logical_name: String,
+    /// Where the contents of the media file come from
+    pub source: MediaSource,
+}
+
+/// Where the contents of a media file come from
+#[derive(Clone, Debug)]
+pub enum MediaSource {
+    /// A file on disk
+    Path(PathBuf),
+    /// Raw bytes kept in memory
+    Bytes(Vec<u8>),
+}
+
+impl MediaFile {
+    /// Creates a media file named `logical_name` in the package with the contents of `source`
+    pub fn new(logical_name: &str, source: MediaSource) -> Self {
+        Self {
+            logical_name: logical_name.to_string(),
+            source,
+        }
+    }
+
+    /// The filename Anki will see for this media file
+    ///
+    /// Returns `Err` if the name is empty, e.g. because a path ends in `..` or its filename is not
+    /// UTF-8, or if it contains a path separator
+    pub(crate) fn filename(&self) -> Result<&str, Error> {
+        if self.logical_name.is_empty() || self.logical_name.contains(['/', '\\'].as_ref()) {
+            Err(Error::InvalidMediaPath(match &self.source {
+                MediaSource::Path(path) if self.logical_name.is_empty() => path.clone(),
+                _ => PathBuf::from(&self.logical_name),
+            }))
+        } else {
+            Ok(&self.logical_name)
+        }
+    }
+
+    /// Checks that the media file has a valid filename and that an existing path is a regular file
+    ///
+    /// Missing files are accepted, they are reported by [`Package::verify_media`] or when writing.
+    ///
+    /// Returns `Err` like [`MediaFile::filename`], [`Error::NotAFile`] if a path points to e.g. a
+    /// directory or a device, or [`Error::MediaRead`] if the metadata of a path cannot be read
+    ///
+    /// [`Package::verify_media`]: crate::Package::verify_media
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        self.filename()?;
+        match &self.source {
+            MediaSource::Path(path) => match std::fs::metadata(path) {
+                Ok(metadata) if metadata.is_file() => Ok(()),
+                Ok(_) => Err(Error::NotAFile(path.clone())),
+                Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+                Err(source) => Err(media_read_error(path, source)),
+            },
+            MediaSource::Bytes(_) => Ok(()),
+        }
+    }
+}
+
+impl From<PathBuf> for MediaFile {
+    /// Creates a media file on disk, named after the basename of `path`
+    fn from(path: PathBuf) -> Self {
+        let logical_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        Self {
+            logical_name,
+            source: MediaSource::Path(path),
+        }
+    }
+}
+
+impl From<&str> for MediaFile {
+    /// Creates a media file on disk, named after the basename of `path`
+    fn from(path: &str) -> Self {
+        PathBuf::from(path).into()
+    }
+}
+
+impl TryFrom<&Path> for MediaFile {
+    type Error = Error;
+
+    /// Creates a media file on disk, named after the basename of `path`, checking that `path`
+    /// ends in a valid filename and, if it exists, is a regular file
+    fn try_from(path: &Path) -> Result<Self, Error> {
+        let media_file = MediaFile::from(path.to_path_buf());
+        media_file.validate()?;
+        Ok(media_file)
+    }
+}
+
+impl MediaSource {
+    /// Returns the path of a file on disk which does not exist or cannot be opened for reading
+    pub(crate) fn unreadable_path(&self) -> Option<&PathBuf> {
+        match self {
+            MediaSource::Path(path) if File::open(path).is_err() => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The size of the contents of the media file in bytes
+    pub(crate) fn size(&self) -> Result<u64, Error> {
+        match self {
+            MediaSource::Path(path) => Ok(path
+                .metadata()
+                .map_err(|source| media_read_error(path, source))?
+                .len()),
+            MediaSource::Bytes(data) => Ok(data.len() as u64),
+        }
+    }
+
+    /// The sha1 hash of the contents of the media file
+    pub(crate) fn content_hash(&self) -> Result<Vec<u8>, Error> {
+        let mut hasher = Sha1::new();
+        match self {
+            MediaSource::Path(path) => {
+                File::open(path)
+                    .and_then(|mut file| std::io::copy(&mut file, &mut hasher))
+                    .map_err(|source| media_read_error(path, source))?;
+            }
+            MediaSource::Bytes(data) => hasher.update(data),
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    /// Writes the contents of the media file to `out`
+    ///
+    /// Files on disk are streamed, so they never have to fit into memory at once. Errors reading
+    /// the file are returned as [`Error::MediaRead`], errors writing to `out` as [`Error::Io`].
+    pub(crate) fn write_to<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        match self {
+            MediaSource::Path(path) => {
+                let mut file = File::open(path).map_err(|source| media_read_error(path, source))?;
+                let mut buffer = [0; 8192];
+                loop {
+                    let read = match file.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                        Err(source) => return Err(media_read_error(path, source)),
+                    };
+                    out.write_all(&buffer[..read])?;
+                }
+            }
+            MediaSource::Bytes(data) => out.write_all(data)?,
+        }
+        Ok(())
+    }
+}
+
+fn media_read_error(path: &Path, source: std::io::Error) -> Error {
+    Error::MediaRead {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Returns the reference Anki uses to play the sound or video `filename`, e.g. `[sound:hello.mp3]`
+///
+/// The filename is inserted as it is, so it must match the name of the media file in the package.
+///
+/// Example:
+/// ```
+/// use genanki_rs::{basic_model, sound, Note};
+///
+/// let model = basic_model();
+/// let note = Note::new(&model, vec![sound("hello.mp3"), "Hello".to_string()]).unwrap();
+/// assert_eq!(note.media_references(), vec!["hello.mp3"]);
+/// ```
+pub fn sound(filename: &str) -> String {
+    format!("[sound:{}]", filename)
+}
+
+/// Returns an `<img>` tag showing the image `filename`, with the alternative text `alt`
+///
+/// The filename must match the name of the media file in the package. Both `filename` and `alt`
+/// are HTML-escaped, so they cannot end the attribute early.
+///
+/// Example:
+/// ```
+/// use genanki_rs::image;
+///
+/// assert_eq!(image("cat.jpg", "A \"cat\""), r#"<img src="cat.jpg" alt="A &quot;cat&quot;">"#);
+/// ```
+pub fn image(filename: &str, alt: &str) -> String {
+    format!(
+        r#"<img src="{}" alt="{}">"#,
+        escape_html(filename),
+        escape_html(alt)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, Note};
+
+    #[test]
+    fn helpers_are_found_as_media_references() {
+        let model = basic_model();
+        let note = Note::new(
+            &model,
+            vec![
+                sound("foo bar #1.mp3"),
+                image("cat & dog.jpg", "<Cat> & dog"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            note.media_references(),
+            vec!["foo bar #1.mp3", "cat & dog.jpg"]
+        );
+        assert_eq!(
+            note.fields()[1],
+            r#"<img src="cat &amp; dog.jpg" alt="&lt;Cat&gt; &amp; dog">"#
+        );
+        assert_eq!(
+            image(r#"a" onerror="x.jpg"#, ""),
+            r#"<img src="a&quot; onerror=&quot;x.jpg" alt="">"#
+        );
+    }
+
+    #[test]
+    fn missing_file_read_error_has_path() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let path = media_dir.path().join("missing.mp3");
+        let source = MediaSource::Path(path.clone());
+        assert!(matches!(
+            source.write_to(&mut vec![]),
+            Err(Error::MediaRead { path: error_path, source })
+                if error_path == path && source.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert!(matches!(
+            source.content_hash(),
+            Err(Error::MediaRead { .. })
+        ));
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_error_is_not_a_read_error() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let path = media_dir.path().join("image.jpg");
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+        assert!(matches!(
+            MediaSource::Path(path).write_to(&mut FailingWriter),
+            Err(Error::Io(error)) if error.kind() == ErrorKind::Other
+        ));
+    }
+
+    #[test]
+    fn directory_is_not_a_file() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        assert!(matches!(
+            MediaFile::try_from(media_dir.path()),
+            Err(Error::NotAFile(path)) if path == media_dir.path()
+        ));
+        let image_path = media_dir.path().join("image.jpg");
+        std::fs::write(&image_path, [1, 2, 3]).unwrap();
+        assert!(MediaFile::try_from(image_path.as_path()).is_ok());
+        assert!(matches!(
+            MediaFile::try_from(Path::new("images/..")),
+            Err(Error::InvalidMediaPath(_))
+        ));
+        assert!(MediaFile::try_from(media_dir.path().join("missing.jpg").as_path()).is_ok());
+        #[cfg(unix)]
+        assert!(matches!(
+            MediaFile::try_from(Path::new("/dev/null")),
+            Err(Error::NotAFile(_))
+        ));
+    }
+}
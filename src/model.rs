@@ -4,41 +4,180 @@ use crate::error::{json_error, template_error};
 use crate::{Error, Field};
 use fancy_regex::Regex;
 use ramhorns::Template as RamTemplate;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{Arc, OnceLock};
 
-const DEFAULT_LATEX_PRE: &str = r#"
-\documentclass[12pt]{article}
+/// Anki's standard LaTeX preamble
+const DEFAULT_LATEX_PRE: &str = r#"\documentclass[12pt]{article}
 \special{papersize=3in,5in}
 \usepackage[utf8]{inputenc}
 \usepackage{amssymb,amsmath}
 \pagestyle{empty}
 \setlength{\parindent}{0in}
 \begin{document}
-
 "#;
+/// Anki's standard LaTeX postamble
 const DEFAULT_LATEX_POST: &str = r"\end{document}";
 
+/// Names Anki replaces in templates which are not fields of the model
+const SPECIAL_FIELDS: &[&str] = &[
+    "FrontSide",
+    "Tags",
+    "Type",
+    "Deck",
+    "Subdeck",
+    "Card",
+    "CardFlag",
+];
+
 /// `FrontBack` or `Cloze` to determine the type of a Model.
 ///
 /// When creating a Model, the default is `FrontBack`
 #[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ModelType {
     FrontBack,
     Cloze,
 }
 
+/// Anki's stock note types, written as `originalStockKind` of models created from them
+#[derive(Clone, Copy)]
+enum StockKind {
+    Basic = 1,
+    BasicAndReversed = 2,
+    BasicTyping = 4,
+}
+
+/// Horizontal alignment of the text on the cards of a `Model`, see [`Model::align`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+    Justify,
+}
+
+impl Alignment {
+    fn css_value(self) -> &'static str {
+        match self {
+            Alignment::Left => "left",
+            Alignment::Center => "center",
+            Alignment::Right => "right",
+            Alignment::Justify => "justify",
+        }
+    }
+}
+
+/// Writing direction of the text on the cards of a `Model`, see [`Model::direction`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextDirection {
+    /// Left-to-right, e.g. for English
+    Ltr,
+    /// Right-to-left, e.g. for Arabic or Hebrew
+    Rtl,
+}
+
+impl TextDirection {
+    fn css_value(self) -> &'static str {
+        match self {
+            TextDirection::Ltr => "ltr",
+            TextDirection::Rtl => "rtl",
+        }
+    }
+}
+
+/// Suspicious part of a model found by [`Model::lint`]
+///
+/// Unlike errors, warnings do not stop the model from being written, as there are models which
+/// are intentionally written this way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The answer format of the template is the same as its question format, so the answer shows
+    /// nothing new
+    IdenticalSides { template: String },
+    /// The answer format of the template does not reference `{{FrontSide}}`, so the question is not
+    /// shown with the answer
+    MissingFrontSide { template: String },
+    /// The field is not referenced by any template, so it is never shown
+    UnreferencedField { field: String },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::IdenticalSides { template } => write!(
+                f,
+                "template {:?} has the same question and answer format",
+                template
+            ),
+            Warning::MissingFrontSide { template } => write!(
+                f,
+                "answer format of template {:?} does not reference {{{{FrontSide}}}}",
+                template
+            ),
+            Warning::UnreferencedField { field } => {
+                write!(f, "field {:?} is not referenced by any template", field)
+            }
+        }
+    }
+}
+
 /// `Model` to determine the structure of a `Note`
+///
+/// With the `serde` feature, models implement `Serialize` and `Deserialize`, e.g. to define them
+/// in a config file. Fields and templates take the names of their builder methods, and everything
+/// except `id`, `name`, `fields` and `templates` is optional and left out when it has the default
+/// value:
+///
+/// ```json
+/// {
+///   "id": 1607392319,
+///   "name": "Simple Model",
+///   "fields": [{ "name": "Question" }, { "name": "Answer", "font": "Courier", "rtl": true }],
+///   "templates": [{ "name": "Card 1", "qfmt": "{{Question}}", "afmt": "{{Answer}}" }],
+///   "css": ".card { color: black; }",
+///   "model_type": "Cloze",
+///   "latex_pre": "...",
+///   "latex_post": "...",
+///   "sort_field_index": 1,
+///   "align": "Center",
+///   "direction": "Rtl",
+///   "default_deck": 2059400110,
+///   "modified": 1600000000
+/// }
+/// ```
+///
+/// Options Anki stores in a model which cannot be set with the builder methods, e.g. those of
+/// models read with [`crate::Package::read_from_file`], are not serialized.
 #[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "ModelSpec", into = "ModelSpec")
+)]
 pub struct Model {
     pub id: i64,
     name: String,
     fields: Vec<Fld>,
     templates: Vec<Tmpl>,
     css: String,
+    align: Option<Alignment>,
+    direction: Option<TextDirection>,
     model_type: ModelType,
     latex_pre: String,
     latex_post: String,
     sort_field_index: i64,
+    default_deck: Option<i64>,
+    modified: Option<i64>,
+    vers: Vec<Option<serde_json::Value>>,
+    tags: Vec<Option<serde_json::Value>>,
+    other: serde_json::Map<String, serde_json::Value>,
+    /// The front sides of `templates` parsed by ramhorns, see [`Model::parsed_qfmts`]
+    parsed_qfmts: OnceLock<Arc<Vec<RamTemplate<'static>>>>,
 }
 
 impl Model {
@@ -57,17 +196,47 @@ impl Model {
     ///         .afmt(r#"{{FrontSide}}<hr id="answer">{{Answer}}"#)],
     /// );
     /// ```
-    pub fn new(id: i64, name: &str, fields: Vec<Field>, templates: Vec<Template>) -> Self {
+    ///
+    /// `fields` and `templates` can be anything iterable, e.g. a `Vec` or an iterator chain:
+    ///
+    /// ```
+    /// use genanki_rs::{Model, Field, Template};
+    /// let languages = ["English", "German", "French"];
+    /// let model = Model::new(
+    ///     1607392320,
+    ///     "Vocabulary",
+    ///     languages.iter().map(|language| Field::new(language)),
+    ///     languages.iter().skip(1).map(|language| {
+    ///         Template::new(&format!("English to {}", language))
+    ///             .qfmt("{{English}}")
+    ///             .afmt(&format!("{{{{{}}}}}", language))
+    ///     }),
+    /// );
+    /// ```
+    pub fn new(
+        id: i64,
+        name: &str,
+        fields: impl IntoIterator<Item = Field>,
+        templates: impl IntoIterator<Item = Template>,
+    ) -> Self {
         Self {
             id,
             name: name.to_string(),
-            fields: fields.iter().cloned().map(|f| f.into()).collect(),
-            templates: templates.iter().cloned().map(|t| t.into()).collect(),
+            fields: fields.into_iter().map(Fld::from).collect(),
+            templates: template_db_entries(templates),
             css: "".to_string(),
+            align: None,
+            direction: None,
             model_type: ModelType::FrontBack,
             latex_pre: DEFAULT_LATEX_PRE.to_string(),
             latex_post: DEFAULT_LATEX_POST.to_string(),
             sort_field_index: 0,
+            default_deck: None,
+            modified: None,
+            vers: vec![],
+            tags: vec![],
+            other: serde_json::Map::new(),
+            parsed_qfmts: OnceLock::new(),
         }
     }
 
@@ -81,8 +250,8 @@ impl Model {
     pub fn new_with_options(
         id: i64,
         name: &str,
-        fields: Vec<Field>,
-        templates: Vec<Template>,
+        fields: impl IntoIterator<Item = Field>,
+        templates: impl IntoIterator<Item = Template>,
         css: Option<&str>,
         model_type: Option<ModelType>,
         latex_pre: Option<&str>,
@@ -92,25 +261,101 @@ impl Model {
         Self {
             id,
             name: name.to_string(),
-            fields: fields.iter().cloned().map(|f| f.into()).collect(),
-            templates: templates.iter().cloned().map(|t| t.into()).collect(),
+            fields: fields.into_iter().map(Fld::from).collect(),
+            templates: template_db_entries(templates),
             css: css.unwrap_or("").to_string(),
+            align: None,
+            direction: None,
             model_type: model_type.unwrap_or(ModelType::FrontBack),
             latex_pre: latex_pre.unwrap_or(DEFAULT_LATEX_PRE).to_string(),
             latex_post: latex_post.unwrap_or(DEFAULT_LATEX_POST).to_string(),
             sort_field_index: sort_field_index.unwrap_or(0),
+            default_deck: None,
+            modified: None,
+            vers: vec![],
+            tags: vec![],
+            other: serde_json::Map::new(),
+            parsed_qfmts: OnceLock::new(),
+        }
+    }
+
+    /// Creates Anki's stock "Basic" model with a `Front` and a `Back` field and one card
+    ///
+    /// The fields and templates match the note type of the same name in Anki, so the cards look
+    /// the same as the ones created in Anki. Use a unique `id` for each model.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Model, Note};
+    ///
+    /// let model = Model::basic(1607392319);
+    /// let note = Note::new(&model, vec!["Capital of France", "Paris"]).unwrap();
+    /// ```
+    pub fn basic(id: i64) -> Self {
+        crate::basic_model()
+            .clone_with_id(id, "Basic")
+            .original_stock_kind(StockKind::Basic)
+    }
+
+    /// Creates Anki's stock "Basic (and reversed card)" model, with a card for each direction
+    ///
+    /// See [`Model::basic`].
+    pub fn basic_and_reversed(id: i64) -> Self {
+        crate::basic_and_reversed_card_model()
+            .clone_with_id(id, "Basic (and reversed card)")
+            .original_stock_kind(StockKind::BasicAndReversed)
+    }
+
+    /// Creates Anki's stock "Basic (type in the answer)" model, which asks to type the `Back`
+    ///
+    /// See [`Model::basic`].
+    pub fn basic_type_in_answer(id: i64) -> Self {
+        crate::basic_type_in_the_answer_model()
+            .clone_with_id(id, "Basic (type in the answer)")
+            .original_stock_kind(StockKind::BasicTyping)
+    }
+
+    /// Records which of Anki's stock note types this model was created from
+    fn original_stock_kind(mut self, kind: StockKind) -> Self {
+        self.other
+            .insert("originalStockKind".to_string(), (kind as i64).into());
+        self
+    }
+
+    /// Returns a copy of this model with a new `id` and `name`
+    ///
+    /// Fields, templates, css and all other options are copied, so the clone can be changed
+    /// without affecting this model, e.g. to create variants of a model with a different layout.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::basic_model;
+    ///
+    /// let model = basic_model();
+    /// let variant = model
+    ///     .clone_with_id(1607392321, "Basic (large)")
+    ///     .css(".card { font-size: 40px; }");
+    /// ```
+    pub fn clone_with_id(&self, new_id: i64, new_name: &str) -> Model {
+        Model {
+            id: new_id,
+            name: new_name.to_string(),
+            ..self.clone()
         }
     }
 
     /// Adds an additional field to the model
     pub fn with_field(mut self, field: Field) -> Self {
         self.fields.push(field.into());
+        self.parsed_qfmts = OnceLock::new();
         self
     }
 
     /// Adds an additional template to the model
     pub fn with_template(mut self, template: Template) -> Self {
-        self.templates.push(template.into());
+        self.templates
+            .push(template_db_entry(template, self.templates.len()));
+        self.parsed_qfmts = OnceLock::new();
         self
     }
 
@@ -122,6 +367,66 @@ impl Model {
         }
     }
 
+    /// Sets the horizontal alignment of the text on the cards of this model
+    ///
+    /// This appends a `.card { text-align: ... }` rule to the CSS of the model. As it comes after
+    /// the CSS set with [`Model::css`], it overrides a `text-align` of `.card` set there, while
+    /// more specific selectors in the custom CSS still take precedence.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{basic_model, Alignment, TextDirection};
+    ///
+    /// let model = basic_model()
+    ///     .align(Alignment::Right)
+    ///     .direction(TextDirection::Rtl);
+    /// ```
+    pub fn align(self, align: Alignment) -> Self {
+        Self {
+            align: Some(align),
+            ..self
+        }
+    }
+
+    /// Sets the writing direction of the text on the cards of this model
+    ///
+    /// Like [`Model::align`], this appends a `.card { direction: ... }` rule after the custom CSS.
+    /// To edit a single field right-to-left, use [`Field::rtl`] instead.
+    pub fn direction(self, direction: TextDirection) -> Self {
+        Self {
+            direction: Some(direction),
+            ..self
+        }
+    }
+
+    /// Returns the custom CSS followed by the rules of [`Model::align`] and [`Model::direction`]
+    fn full_css(&self) -> String {
+        let declarations = [
+            self.align
+                .map(|align| format!("text-align: {};", align.css_value())),
+            self.direction
+                .map(|direction| format!("direction: {};", direction.css_value())),
+        ]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+        if declarations.is_empty() {
+            return self.css.clone();
+        }
+        let separator = if self.css.is_empty() || self.css.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        format!(
+            "{}{}.card {{ {} }}\n",
+            self.css,
+            separator,
+            declarations.join(" ")
+        )
+    }
+
     /// Change the type of the model
     pub fn model_type(self, model_type: ModelType) -> Self {
         Self { model_type, ..self }
@@ -144,6 +449,10 @@ impl Model {
     }
 
     /// Sets the index of the field used for sorting with this model
+    ///
+    /// The value of this field is written as the sort field of each note, which Anki uses to sort
+    /// notes in the browser. Writing the model fails with `Error::InvalidSortFieldIndex` if the
+    /// model has no field with this index.
     pub fn sort_field_index(self, sort_field_index: i64) -> Self {
         Self {
             sort_field_index,
@@ -151,32 +460,54 @@ impl Model {
         }
     }
 
+    /// Sets the deck Anki suggests when adding new notes of this model
+    ///
+    /// Without a default deck, Anki suggests the deck the notes of this model are added to in the
+    /// package. Writing the package fails with `Error::MissingDeck` if the deck is not part of it.
+    pub fn default_deck(self, deck_id: i64) -> Self {
+        Self {
+            default_deck: Some(deck_id),
+            ..self
+        }
+    }
+
+    pub(crate) fn default_deck_id(&self) -> Option<i64> {
+        self.default_deck
+    }
+
+    /// Sets the time the model was last modified, in seconds since the unix epoch
+    ///
+    /// Anki only updates a model which already exists in the collection when importing if the
+    /// modification time is newer. Defaults to the timestamp the package is written with.
+    pub fn modified(self, modified: i64) -> Self {
+        Self {
+            modified: Some(modified),
+            ..self
+        }
+    }
+
+    /// Computes the fields required by each template, which old Anki versions use to generate cards
+    ///
+    /// This follows Anki's legacy algorithm: a field is required (`"all"`) if the front side shows
+    /// no field content when only this field is empty. If no field is required, any of the fields
+    /// (`"any"`) which show content on their own is enough to generate a card.
     pub(super) fn req(&self) -> Result<Vec<(usize, String, Vec<usize>)>, Error> {
-        let sentinel = "SeNtInEl".to_string();
-        let field_names: Vec<String> = self.fields.iter().map(|field| field.name.clone()).collect();
-        let field_values = field_names
-            .iter()
-            .map(|field| (field.as_str(), format!("{}{}", &field, &sentinel)));
         let mut req = Vec::new();
-        for (template_ord, template) in self.templates.iter().enumerate() {
-            let rendered = RamTemplate::new(template.qfmt.clone())
-                .map_err(template_error)?
-                .render::<HashMap<&str, String>>(&field_values.clone().collect());
-            let required_fields = field_values
-                .clone()
-                .enumerate()
-                .filter(|(_, (_, field))| !contains_other_fields(&rendered, field, &sentinel))
-                .map(|(field_ord, _)| field_ord)
+        for (template, qfmt) in self.templates.iter().zip(self.parsed_qfmts()?.iter()) {
+            let template_ord = template.ord as usize;
+            let required_fields = (0..self.fields.len())
+                .filter(|&empty_ord| {
+                    !shows_field_content(qfmt, self.fields.len(), |ord| ord != empty_ord)
+                })
                 .collect::<Vec<_>>();
             if !required_fields.is_empty() {
                 req.push((template_ord, "all".to_string(), required_fields));
                 continue;
             }
-            let required_fields = field_values
-                .clone()
-                .enumerate()
-                .filter(|(_, (_, sentinel))| rendered.contains(sentinel))
-                .map(|(field_ord, _)| field_ord)
+            let required_fields = (0..self.fields.len())
+                .filter(|&filled_ord| {
+                    shows_field_content(qfmt, self.fields.len(), |ord| ord == filled_ord)
+                })
                 .collect::<Vec<_>>();
             if required_fields.is_empty() {
                 return Err(Error::TemplateFormat(template.clone()));
@@ -186,6 +517,179 @@ impl Model {
         Ok(req)
     }
 
+    /// Returns the ordinals of the templates whose front side shows any of the non-empty `fields`
+    ///
+    /// This is the rule Anki uses to decide whether a card is generated: static text in the
+    /// template does not count, while conditional sections like `{{#Field}}...{{/Field}}` are
+    /// evaluated with the note's fields. Like in Anki, fields which only contain whitespace,
+    /// `<br>` or `<div>` tags are empty.
+    pub(super) fn non_empty_front_ords(&self, fields: &[String]) -> Result<Vec<i64>, Error> {
+        Ok(self
+            .templates
+            .iter()
+            .zip(self.parsed_qfmts()?.iter())
+            .filter(|(_, qfmt)| {
+                shows_field_content(qfmt, fields.len(), |ord| !field_is_empty(&fields[ord]))
+            })
+            .map(|(template, _)| template.ord)
+            .collect())
+    }
+
+    /// Returns the front sides of the templates parsed by ramhorns
+    ///
+    /// They are parsed when a note of the model is first created and then reused for all other
+    /// notes.
+    fn parsed_qfmts(&self) -> Result<&[RamTemplate<'static>], Error> {
+        if let Some(parsed_qfmts) = self.parsed_qfmts.get() {
+            return Ok(parsed_qfmts);
+        }
+        let parsed_qfmts = self
+            .templates
+            .iter()
+            .map(|template| {
+                RamTemplate::new(self.normalize_field_names(&template.qfmt)).map_err(template_error)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.parsed_qfmts.get_or_init(|| Arc::new(parsed_qfmts)))
+    }
+
+    /// Checks that the templates only reference fields of this model
+    ///
+    /// References in `qfmt` and `afmt`, including conditionals like `{{#Field}}` and filters like
+    /// `{{hint:Field}}` or `{{type:Field}}`, must name a field of the model or one of Anki's
+    /// special fields like `{{FrontSide}}`, `{{Tags}}`, `{{Type}}`, `{{Deck}}`, `{{Subdeck}}`,
+    /// `{{Card}}` or `{{CardFlag}}`. This catches typos which would otherwise produce blank cards.
+    ///
+    /// Returns [`Error::UnknownFieldReference`] for the first unknown reference
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Error, Field, Model, Template};
+    ///
+    /// let model = Model::new(
+    ///     1607392319,
+    ///     "Simple Model",
+    ///     vec![Field::new("Question"), Field::new("Answer")],
+    ///     vec![Template::new("Card 1")
+    ///         .qfmt("{{Question}}")
+    ///         .afmt("{{FrontSide}}<hr id=answer>{{Answr}}")],
+    /// );
+    /// assert!(matches!(
+    ///     model.validate(),
+    ///     Err(Error::UnknownFieldReference { field, .. }) if field == "Answr"
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), Error> {
+        for template in &self.templates {
+            for format in [&template.qfmt, &template.afmt] {
+                for name in referenced_names(format) {
+                    if name.is_empty()
+                        || SPECIAL_FIELDS.contains(&name.as_str())
+                        || self.fields.iter().any(|field| field.name == name)
+                    {
+                        continue;
+                    }
+                    return Err(Error::UnknownFieldReference {
+                        template: template.name.clone(),
+                        field: name,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns warnings for parts of the model which are valid but likely a mistake
+    ///
+    /// Templates are checked for an answer format identical to the question format and for an
+    /// answer format without `{{FrontSide}}`, which are skipped for cloze models as they show the
+    /// same cloze on both sides. Fields which no template references are reported as well.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Field, Model, Template, Warning};
+    ///
+    /// let model = Model::new(
+    ///     1607392319,
+    ///     "Simple Model",
+    ///     vec![Field::new("Question"), Field::new("Answer")],
+    ///     vec![Template::new("Card 1").qfmt("{{Question}}").afmt("{{Question}}")],
+    /// );
+    /// for warning in model.lint() {
+    ///     println!("Warning: {}", warning);
+    /// }
+    /// assert_eq!(
+    ///     model.lint()[2],
+    ///     Warning::UnreferencedField { field: "Answer".to_string() }
+    /// );
+    /// ```
+    pub fn lint(&self) -> Vec<Warning> {
+        let mut warnings = vec![];
+        if self.model_type != ModelType::Cloze {
+            for template in &self.templates {
+                if template.qfmt.trim() == template.afmt.trim() {
+                    warnings.push(Warning::IdenticalSides {
+                        template: template.name.clone(),
+                    });
+                }
+                if !referenced_names(&template.afmt).contains(&"FrontSide".to_string()) {
+                    warnings.push(Warning::MissingFrontSide {
+                        template: template.name.clone(),
+                    });
+                }
+            }
+        }
+        let referenced = self
+            .templates
+            .iter()
+            .flat_map(|template| {
+                referenced_names(&template.qfmt)
+                    .into_iter()
+                    .chain(referenced_names(&template.afmt))
+            })
+            .collect::<HashSet<_>>();
+        for field in &self.fields {
+            if !referenced.contains(&field.name) {
+                warnings.push(Warning::UnreferencedField {
+                    field: field.name.clone(),
+                });
+            }
+        }
+        warnings
+    }
+
+    /// Replaces field references in `format` with keys that can be rendered by ramhorns
+    ///
+    /// Field names may contain spaces and be prefixed with filters like `hint:`, which ramhorns
+    /// does not understand. References to anything other than a field of this model, e.g.
+    /// `{{Tags}}`, are replaced with a key that never has a value.
+    fn normalize_field_names(&self, format: &str) -> String {
+        template_reference_regex()
+            .replace_all(format, |caps: &fancy_regex::Captures| {
+                let name = caps[2].rsplit(':').next().unwrap_or_default().trim();
+                let key = self
+                    .fields
+                    .iter()
+                    .position(|field| field.name == name)
+                    .map(field_key)
+                    .unwrap_or_else(|| "unknown".to_string());
+                format!("{{{{{}{}}}}}", &caps[1], key)
+            })
+            .to_string()
+    }
+
+    /// Returns the value of the sort field among the `fields` of a note of this model
+    pub(super) fn sort_field_of<'f>(&self, fields: &'f [String]) -> &'f str {
+        usize::try_from(self.sort_field_index)
+            .ok()
+            .and_then(|index| fields.get(index))
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
     pub(super) fn fields(&self) -> Vec<Fld> {
         self.fields.clone()
     }
@@ -196,66 +700,258 @@ impl Model {
         self.model_type.clone()
     }
     pub(super) fn to_model_db_entry(
-        &mut self,
+        &self,
         timestamp: f64,
         deck_id: i64,
     ) -> Result<ModelDbEntry, Error> {
-        self.templates
-            .iter_mut()
-            .enumerate()
-            .for_each(|(i, template)| {
-                template.ord = i as i64;
+        if self.sort_field_index < 0 || self.sort_field_index as usize >= self.fields.len() {
+            return Err(Error::InvalidSortFieldIndex {
+                model: self.name.clone(),
+                index: self.sort_field_index,
+                num_fields: self.fields.len(),
             });
-        self.fields.iter_mut().enumerate().for_each(|(i, field)| {
-            field.ord = i as i64;
-        });
+        }
+        let mut ords = HashSet::new();
+        for template in &self.templates {
+            if template.ord < 0
+                || template.ord as usize >= self.templates.len()
+                || !ords.insert(template.ord)
+            {
+                return Err(Error::InvalidTemplateOrd {
+                    model: self.name.clone(),
+                    ord: template.ord,
+                });
+            }
+        }
+        // Anki expects the templates to be stored in the order of their ordinals
+        let mut templates = self.templates.clone();
+        templates.sort_by_key(|template| template.ord);
+        let fields = self
+            .fields
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, field)| Fld {
+                ord: i as i64,
+                ..field
+            })
+            .collect();
         let model_type = match self.model_type {
             ModelType::FrontBack => 0,
             ModelType::Cloze => 1,
         };
         Ok(ModelDbEntry {
-            vers: vec![],
+            vers: self.vers.clone(),
             name: self.name.clone(),
-            tags: vec![],
-            did: deck_id,
+            tags: self.tags.clone(),
+            did: self.default_deck.unwrap_or(deck_id),
             usn: -1,
-            req: self.req()?.clone(),
-            flds: self.fields.clone(),
-            sortf: self.sort_field_index.clone(),
-            tmpls: self.templates.clone(),
-            model_db_entry_mod: timestamp as i64,
+            req: self.req()?,
+            flds: fields,
+            sortf: self.sort_field_index,
+            tmpls: templates,
+            model_db_entry_mod: self.modified.unwrap_or(timestamp as i64),
             latex_post: self.latex_post.clone(),
             model_db_entry_type: model_type,
             id: self.id.to_string(),
-            css: self.css.clone(),
+            css: self.full_css(),
             latex_pre: self.latex_pre.clone(),
+            other: self.other.clone(),
+        })
+    }
+
+    /// Reconstructs a model from its entry in the `models` JSON of a collection
+    pub(super) fn from_model_db_entry(entry: ModelDbEntry) -> Result<Self, Error> {
+        Ok(Self {
+            id: entry
+                .id
+                .parse()
+                .map_err(|e| Error::JsonParser(Box::new(e)))?,
+            name: entry.name,
+            fields: entry.flds,
+            templates: entry.tmpls,
+            css: entry.css,
+            align: None,
+            direction: None,
+            model_type: match entry.model_db_entry_type {
+                1 => ModelType::Cloze,
+                _ => ModelType::FrontBack,
+            },
+            latex_pre: entry.latex_pre,
+            latex_post: entry.latex_post,
+            sort_field_index: entry.sortf,
+            default_deck: None,
+            modified: None,
+            vers: entry.vers,
+            tags: entry.tags,
+            other: entry.other,
+            parsed_qfmts: OnceLock::new(),
         })
     }
 
     #[allow(dead_code)]
-    pub(super) fn to_json(&mut self, timestamp: f64, deck_id: i64) -> Result<String, Error> {
-        Ok(
-            serde_json::to_string(&self.to_model_db_entry(timestamp, deck_id)?)
-                .map_err(json_error)?,
-        )
+    pub(super) fn to_json(&self, timestamp: f64, deck_id: i64) -> Result<String, Error> {
+        serde_json::to_string(&self.to_model_db_entry(timestamp, deck_id)?).map_err(json_error)
     }
 }
 
-fn contains_other_fields(rendered: &str, current_field: &str, sentinel: &str) -> bool {
-    Regex::new(&format!(
-        "(?!{field}\\b)\\b(\\w)*{sentinel}+",
-        field = current_field,
-        sentinel = sentinel
-    ))
-    .unwrap()
-    .is_match(rendered)
-    .unwrap()
+fn template_db_entries(templates: impl IntoIterator<Item = Template>) -> Vec<Tmpl> {
+    templates
+        .into_iter()
+        .enumerate()
+        .map(|(position, template)| template_db_entry(template, position))
+        .collect()
+}
+
+/// Converts `template`, using its `position` in the model as ordinal unless one is set explicitly
+fn template_db_entry(template: Template, position: usize) -> Tmpl {
+    let ord = template.get_ord().unwrap_or(position as i64);
+    Tmpl {
+        ord,
+        ..template.into()
+    }
+}
+
+/// Serialized form of a `Model`, see [`Model`]
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ModelSpec {
+    id: i64,
+    name: String,
+    fields: Vec<Field>,
+    templates: Vec<Template>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    css: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model_type: Option<ModelType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latex_pre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latex_post: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort_field_index: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    align: Option<Alignment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    direction: Option<TextDirection>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_deck: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<i64>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Model> for ModelSpec {
+    fn from(model: Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            fields: model.fields.into_iter().map(Field::from).collect(),
+            templates: model
+                .templates
+                .into_iter()
+                .enumerate()
+                .map(|(position, tmpl)| Template::from_db_entry(tmpl, position))
+                .collect(),
+            css: model.css,
+            model_type: Some(model.model_type)
+                .filter(|model_type| *model_type != ModelType::FrontBack),
+            latex_pre: Some(model.latex_pre).filter(|latex_pre| latex_pre != DEFAULT_LATEX_PRE),
+            latex_post: Some(model.latex_post)
+                .filter(|latex_post| latex_post != DEFAULT_LATEX_POST),
+            sort_field_index: Some(model.sort_field_index).filter(|&index| index != 0),
+            align: model.align,
+            direction: model.direction,
+            default_deck: model.default_deck,
+            modified: model.modified,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ModelSpec> for Model {
+    fn from(spec: ModelSpec) -> Self {
+        Model {
+            align: spec.align,
+            direction: spec.direction,
+            default_deck: spec.default_deck,
+            modified: spec.modified,
+            ..Model::new_with_options(
+                spec.id,
+                &spec.name,
+                spec.fields,
+                spec.templates,
+                Some(&spec.css),
+                spec.model_type,
+                spec.latex_pre.as_deref(),
+                spec.latex_post.as_deref(),
+                spec.sort_field_index,
+            )
+        }
+    }
+}
+
+/// Matches a reference like `{{Field}}`, `{{#Field}}` or `{{hint:Field}}` in a template, with the
+/// conditional prefix as the first group and the name with its filters as the second group
+fn template_reference_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"{{([#^/]?)([^{}]*)}}").expect("static regex"))
+}
+
+/// Returns the names referenced in `format`, without conditionals like `#` and filters like `hint:`
+fn referenced_names(format: &str) -> Vec<String> {
+    template_reference_regex()
+        .captures_iter(format)
+        .filter_map(Result::ok)
+        .map(|caps| {
+            caps[2]
+                .rsplit(':')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Key of the field with ordinal `ord` when rendering templates
+fn field_key(ord: usize) -> String {
+    format!("f{}", ord)
+}
+
+/// Returns whether Anki treats `field` as empty, i.e. it only contains whitespace, `<br>` or
+/// `<div>` tags
+fn field_is_empty(field: &str) -> bool {
+    static EMPTY_FIELD: OnceLock<Regex> = OnceLock::new();
+    EMPTY_FIELD
+        .get_or_init(|| {
+            Regex::new(r"(?xsi)^(?:[[:space:]]|</?(?:br|div)\ ?/?>)*$").expect("static regex")
+        })
+        .is_match(field)
+        .unwrap_or(false)
+}
+
+/// Returns whether `template` shows any field content when the fields among the first
+/// `num_fields` for which `is_filled` returns `true` are filled and all others are empty
+fn shows_field_content(
+    template: &RamTemplate,
+    num_fields: usize,
+    is_filled: impl Fn(usize) -> bool,
+) -> bool {
+    let sentinel = "SeNtInEl";
+    let field_values = (0..num_fields)
+        .filter(|&ord| is_filled(ord))
+        .map(|ord| (field_key(ord), sentinel.to_string()))
+        .collect::<HashMap<String, String>>();
+    template.render(&field_values).contains(sentinel)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Deck, Note};
+    use crate::{
+        basic_and_reversed_card_model, basic_model, basic_type_in_the_answer_model, Deck, Note,
+    };
     use std::collections::HashSet;
     use tempfile::NamedTempFile;
 
@@ -311,6 +1007,40 @@ mod tests {
         )
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn model_round_trip() {
+        let model = Model::new(
+            1607392319,
+            "Simple Model",
+            vec![
+                Field::new("Question"),
+                Field::new("Answer").font("Courier").rtl(true),
+            ],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt("{{Answer}}")
+                .bqfmt("Q")],
+        )
+        .css(".card { color: black; }")
+        .default_deck(2059400110);
+        let json = serde_json::to_value(&model).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "id": 1607392319,
+                "name": "Simple Model",
+                "fields": [{ "name": "Question" }, { "name": "Answer", "rtl": true, "font": "Courier" }],
+                "templates": [{ "name": "Card 1", "qfmt": "{{Question}}", "afmt": "{{Answer}}", "bqfmt": "Q" }],
+                "css": ".card { color: black; }",
+                "default_deck": 2059400110,
+            })
+        );
+        let read: Model = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&read).unwrap(), json);
+        assert_eq!(read.default_deck_id(), Some(2059400110));
+    }
+
     #[test]
     fn cloze() {
         let mut notes = vec![];
@@ -438,6 +1168,509 @@ mod tests {
         assert_eq!(sorted, vec![0, 1]);
     }
 
+    #[test]
+    fn cloze_model_db_entry() {
+        let model = cloze_model();
+        let db_entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(db_entry.model_db_entry_type, 1);
+
+        let front_back = Model::new(1, "front back", vec![Field::new("Front")], vec![]);
+        let db_entry = front_back.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(db_entry.model_db_entry_type, 0);
+    }
+
+    #[test]
+    fn css_in_model_db_entry() {
+        let model = Model::new(1, "css model", vec![Field::new("Front")], vec![]);
+        assert_eq!(model.to_model_db_entry(0.0, 1).unwrap().css, "");
+
+        let model = model.css(css());
+        assert_eq!(model.to_model_db_entry(0.0, 1).unwrap().css, css());
+    }
+
+    #[test]
+    fn browser_formats_in_model_json() {
+        let model = Model::new(
+            1,
+            "browser model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![
+                Template::new("Card 1").qfmt("{{Front}}").afmt("{{Back}}"),
+                Template::new("Card 2")
+                    .qfmt("{{Back}}")
+                    .afmt("{{Front}}")
+                    .bqfmt("{{Back}} (reverse)")
+                    .bafmt("{{Front}}"),
+            ],
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&model.to_json(0.0, 1).unwrap()).unwrap();
+        assert_eq!(json["tmpls"][0]["bqfmt"], "");
+        assert_eq!(json["tmpls"][0]["bafmt"], "");
+        assert_eq!(json["tmpls"][1]["bqfmt"], "{{Back}} (reverse)");
+        assert_eq!(json["tmpls"][1]["bafmt"], "{{Front}}");
+    }
+
+    #[test]
+    fn sort_field_index_in_model_db_entry() {
+        let model = Model::new(
+            1,
+            "sort model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![Template::new("Card 1").qfmt("{{Front}}").afmt("{{Back}}")],
+        );
+        assert_eq!(model.to_model_db_entry(0.0, 1).unwrap().sortf, 0);
+
+        let model = model.sort_field_index(1);
+        assert_eq!(model.to_model_db_entry(0.0, 1).unwrap().sortf, 1);
+
+        let model = model.sort_field_index(2);
+        assert!(matches!(
+            model.to_model_db_entry(0.0, 1),
+            Err(Error::InvalidSortFieldIndex {
+                index: 2,
+                num_fields: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn explicit_template_ords() {
+        let model = Model::new(
+            1,
+            "ord model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![
+                Template::new("Reverse")
+                    .qfmt("{{Back}}")
+                    .afmt("{{Front}}")
+                    .ord(1),
+                Template::new("Forward")
+                    .qfmt("{{Front}}")
+                    .afmt("{{Back}}")
+                    .ord(0),
+            ],
+        );
+        let db_entry = model.to_model_db_entry(0.0, 1).unwrap();
+        let tmpls = db_entry
+            .tmpls
+            .iter()
+            .map(|tmpl| (tmpl.ord, tmpl.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(tmpls, vec![(0, "Forward"), (1, "Reverse")]);
+
+        let note = Note::new(&model, vec!["", "Back"]).unwrap();
+        let card_ords = note
+            .cards()
+            .iter()
+            .map(|card| card.ord())
+            .collect::<Vec<_>>();
+        assert_eq!(card_ords, vec![1]);
+
+        let model = model.with_template(Template::new("Duplicate").qfmt("{{Front}}").ord(1));
+        assert!(matches!(
+            model.to_model_db_entry(0.0, 1),
+            Err(Error::InvalidTemplateOrd { ord: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn align_and_direction_in_css() {
+        let model = Model::new(1, "css model", vec![Field::new("Front")], vec![]);
+        let css = |model: &Model| model.to_model_db_entry(0.0, 1).unwrap().css;
+        assert_eq!(
+            css(&model.clone().align(Alignment::Center)),
+            ".card { text-align: center; }\n"
+        );
+        let model = model
+            .css(".card { text-align: left; }")
+            .align(Alignment::Right)
+            .direction(TextDirection::Rtl);
+        assert_eq!(
+            css(&model),
+            ".card { text-align: left; }\n.card { text-align: right; direction: rtl; }\n"
+        );
+    }
+
+    #[test]
+    fn field_options_in_model_json() {
+        let model = Model::new(
+            1,
+            "field model",
+            vec![
+                Field::new("English"),
+                Field::new("Arabic")
+                    .rtl(true)
+                    .font("Noto Naskh Arabic")
+                    .size(28),
+                Field::new("Source")
+                    .sticky(true)
+                    .description("Where the sentence was found"),
+            ],
+            vec![Template::new("Card 1")
+                .qfmt("{{English}}")
+                .afmt("{{Arabic}}")],
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&model.to_json(0.0, 1).unwrap()).unwrap();
+        let flds = &json["flds"];
+        assert_eq!(flds[0]["font"], "Arial");
+        assert_eq!(flds[0]["size"], 20);
+        assert_eq!(flds[0]["rtl"], false);
+        assert_eq!(flds[0]["sticky"], false);
+        assert_eq!(flds[1]["font"], "Noto Naskh Arabic");
+        assert_eq!(flds[1]["size"], 28);
+        assert_eq!(flds[1]["rtl"], true);
+        assert_eq!(flds[2]["sticky"], true);
+        assert_eq!(flds[2]["ord"], 2);
+        assert_eq!(flds[0]["description"], "");
+        assert_eq!(flds[2]["description"], "Where the sentence was found");
+    }
+
+    #[test]
+    fn modified_in_model_db_entry() {
+        let model = Model::new(1, "mod model", vec![Field::new("Front")], vec![]);
+        assert_eq!(
+            model
+                .to_model_db_entry(1_600_000_000.5, 1)
+                .unwrap()
+                .model_db_entry_mod,
+            1_600_000_000
+        );
+        let model = model.modified(1_500_000_000);
+        assert_eq!(
+            model
+                .to_model_db_entry(1_600_000_000.5, 1)
+                .unwrap()
+                .model_db_entry_mod,
+            1_500_000_000
+        );
+    }
+
+    #[test]
+    fn lint_warnings() {
+        assert!(basic_model().lint().is_empty());
+        assert!(crate::cloze_model().lint().is_empty());
+
+        let model = Model::new(
+            1607392319,
+            "Simple Model",
+            vec![
+                Field::new("Question"),
+                Field::new("Answer"),
+                Field::new("Notes"),
+            ],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Question}}")
+                    .afmt("{{Question}}\n"),
+                Template::new("Card 2")
+                    .qfmt("{{#Answer}}{{Answer}}{{/Answer}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{hint:Question}}"),
+            ],
+        );
+        assert_eq!(
+            model.lint(),
+            vec![
+                Warning::IdenticalSides {
+                    template: "Card 1".to_string()
+                },
+                Warning::MissingFrontSide {
+                    template: "Card 1".to_string()
+                },
+                Warning::UnreferencedField {
+                    field: "Notes".to_string()
+                },
+            ]
+        );
+        assert!(model.validate().is_ok());
+    }
+
+    #[test]
+    fn type_in_the_answer_references_field() {
+        let model = Model::new(
+            1,
+            "spelling model",
+            vec![Field::new("Audio"), Field::new("Word")],
+            vec![Template::new("Spell")
+                .qfmt("{{Audio}}{{type:Word}}")
+                .afmt("{{FrontSide}}<hr id=answer>{{type:Word}}")],
+        );
+        assert!(model.validate().is_ok());
+        assert_eq!(
+            model.req().unwrap(),
+            vec![(0, "any".to_string(), vec![0, 1])]
+        );
+        let entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(entry.tmpls[0].qfmt, "{{Audio}}{{type:Word}}");
+        assert_eq!(
+            entry.tmpls[0].afmt,
+            "{{FrontSide}}<hr id=answer>{{type:Word}}"
+        );
+
+        let front_is_non_empty = |fields: [&str; 2]| {
+            let fields = fields
+                .iter()
+                .map(|field| field.to_string())
+                .collect::<Vec<_>>();
+            !model.non_empty_front_ords(&fields).unwrap().is_empty()
+        };
+        assert!(front_is_non_empty(["", "necessary"]));
+        assert!(!front_is_non_empty(["", ""]));
+    }
+
+    #[test]
+    fn fields_with_only_line_breaks_are_empty() {
+        let model = basic_model();
+        let front_ords = |front: &str| {
+            model
+                .non_empty_front_ords(&[front.to_string(), "back".to_string()])
+                .unwrap()
+        };
+        assert!(front_ords("<br>").is_empty());
+        assert!(front_ords("<div></div>").is_empty());
+        assert!(front_ords(" <BR /> <div>\n</div><br/>").is_empty());
+        assert_eq!(front_ords("<div>front</div>"), vec![0]);
+        assert_eq!(front_ords("<b></b>"), vec![0]);
+
+        // The templates parsed above must not be reused for the changed model
+        let reversed = model
+            .clone()
+            .with_template(Template::new("Card 2").qfmt("{{Back}}"));
+        let fields = ["<br>".to_string(), "back".to_string()];
+        assert_eq!(reversed.non_empty_front_ords(&fields).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn req_for_two_templates() {
+        let model = Model::new(
+            1,
+            "req model",
+            vec![
+                Field::new("Front"),
+                Field::new("Back"),
+                Field::new("Add Reverse"),
+            ],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Front}}{{^Front}}{{Back}}{{/Front}}")
+                    .afmt("{{Back}}"),
+                Template::new("Card 2")
+                    .qfmt("{{#Add Reverse}}{{Back}}{{/Add Reverse}}")
+                    .afmt("{{Front}}"),
+            ],
+        );
+        assert_eq!(
+            model.req().unwrap(),
+            vec![
+                (0, "any".to_string(), vec![0, 1]),
+                (1, "all".to_string(), vec![1, 2]),
+            ]
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&basic_and_reversed_card_model().to_json(0.0, 1).unwrap())
+                .unwrap();
+        assert_eq!(
+            json["req"],
+            serde_json::json!([[0, "all", [0]], [1, "all", [1]]])
+        );
+    }
+
+    #[test]
+    fn validate_field_references() {
+        let model = |afmt: &str| {
+            Model::new(
+                1,
+                "validate model",
+                vec![Field::new("Front"), Field::new("Back Side")],
+                vec![
+                    Template::new("Card 1")
+                        .qfmt("{{Front}}")
+                        .afmt("{{Back Side}}"),
+                    Template::new("Card 2").qfmt("{{Back Side}}").afmt(afmt),
+                ],
+            )
+        };
+        assert!(
+            model("{{FrontSide}}<hr>{{#Front}}{{hint:Front}}{{/Front}}{{Tags}} {{Deck}}")
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            model("{{type:Back Side}} {{^Back Side}}{{Subdeck}}{{/Back Side}}")
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            model("{{FrontSide}}{{Frnt}}").validate(),
+            Err(Error::UnknownFieldReference { template, field })
+                if template == "Card 2" && field == "Frnt"
+        ));
+        assert!(matches!(
+            model("{{#Bak}}x{{/Bak}}").validate(),
+            Err(Error::UnknownFieldReference { field, .. }) if field == "Bak"
+        ));
+        assert!(crate::cloze_model().validate().is_ok());
+        assert!(basic_type_in_the_answer_model().validate().is_ok());
+    }
+
+    #[test]
+    fn clone_with_id_is_independent() {
+        let model = basic_and_reversed_card_model();
+        let variant = model
+            .clone_with_id(1234, "Variant")
+            .css(".card { color: red; }")
+            .with_field(Field::new("Extra"));
+        let entry = model.to_model_db_entry(0.0, 1).unwrap();
+        let variant_entry = variant.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(variant_entry.id, "1234");
+        assert_eq!(variant_entry.name, "Variant");
+        assert_eq!(
+            serde_json::to_value(&variant_entry.tmpls).unwrap(),
+            serde_json::to_value(&entry.tmpls).unwrap()
+        );
+        assert_eq!(variant_entry.flds.len(), 3);
+        assert_eq!(entry.flds.len(), 2);
+        assert_eq!(variant_entry.css, ".card { color: red; }");
+        assert!(entry.css.contains("font-family: arial"));
+        assert_eq!(entry.id, model.id.to_string());
+    }
+
+    #[test]
+    fn default_deck_in_model_db_entry() {
+        let model = Model::new(1, "deck model", vec![Field::new("Front")], vec![]);
+        assert_eq!(model.to_model_db_entry(0.0, 1234).unwrap().did, 1234);
+        let model = model.default_deck(5678);
+        assert_eq!(model.to_model_db_entry(0.0, 1234).unwrap().did, 5678);
+    }
+
+    #[test]
+    fn stock_models() {
+        let entry = |model: Model| model.to_model_db_entry(0.0, 1).unwrap();
+        let basic = entry(Model::basic(1234));
+        assert_eq!(basic.id, "1234");
+        assert_eq!(basic.name, "Basic");
+        assert_eq!(basic.other["originalStockKind"], 1);
+        assert_eq!(
+            basic
+                .flds
+                .iter()
+                .map(|fld| fld.name.as_str())
+                .collect::<Vec<_>>(),
+            ["Front", "Back"]
+        );
+        assert_eq!(basic.tmpls[0].qfmt, "{{Front}}");
+        assert_eq!(
+            basic.tmpls[0].afmt,
+            "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}"
+        );
+
+        let reversed = entry(Model::basic_and_reversed(1235));
+        assert_eq!(reversed.name, "Basic (and reversed card)");
+        assert_eq!(reversed.other["originalStockKind"], 2);
+        assert_eq!(reversed.tmpls[1].qfmt, "{{Back}}");
+
+        let typing = entry(Model::basic_type_in_answer(1236));
+        assert_eq!(typing.name, "Basic (type in the answer)");
+        assert_eq!(typing.other["originalStockKind"], 4);
+        assert_eq!(typing.tmpls[0].qfmt, "{{Front}}\n\n{{type:Back}}");
+        assert!(!basic_model()
+            .to_model_db_entry(0.0, 1)
+            .unwrap()
+            .other
+            .contains_key("originalStockKind"));
+    }
+
+    #[test]
+    fn unknown_keys_round_trip() {
+        let model = basic_and_reversed_card_model();
+        let mut json: serde_json::Value =
+            serde_json::from_str(&model.to_json(0.0, 1).unwrap()).unwrap();
+        json["originalStockKind"] = 1.into();
+        json["vers"] = serde_json::json!([1]);
+        let entry: ModelDbEntry = serde_json::from_value(json).unwrap();
+        let model = Model::from_model_db_entry(entry).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&model.to_json(0.0, 1).unwrap()).unwrap();
+        assert_eq!(json["originalStockKind"], 1);
+        assert_eq!(json["vers"], serde_json::json!([1]));
+        assert_eq!(json["tmpls"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn latex_in_model_db_entry() {
+        let model = Model::new(1, "latex model", vec![Field::new("Front")], vec![]);
+        let db_entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert!(db_entry
+            .latex_pre
+            .starts_with("\\documentclass[12pt]{article}\n"));
+        assert!(db_entry.latex_pre.ends_with("\\begin{document}\n"));
+        assert_eq!(db_entry.latex_post, "\\end{document}");
+
+        let model = model
+            .latex_pre("\\documentclass{article}\\begin{document}")
+            .latex_post("\\end{document}%");
+        let db_entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(
+            db_entry.latex_pre,
+            "\\documentclass{article}\\begin{document}"
+        );
+        assert_eq!(db_entry.latex_post, "\\end{document}%");
+    }
+
+    #[test]
+    fn cloze_ordinal_gaps() {
+        let model = cloze_model();
+        let note = Note::new(
+            &model,
+            vec![
+                "{{c5::fifth}} and {{c1::first}} and {{c5::fifth again}}",
+                "",
+            ],
+        )
+        .unwrap();
+        let card_ords = note
+            .cards()
+            .iter()
+            .map(|card| card.ord())
+            .collect::<Vec<_>>();
+        assert_eq!(card_ords, vec![0, 4]);
+    }
+
+    #[test]
+    fn cloze_cards_written_to_db() {
+        let model = cloze_model();
+        let mut deck = Deck::new(0, "test", "");
+        deck.add_note(
+            Note::new(
+                &model,
+                vec!["{{c1::Berlin}} is the capital of {{c2::Germany}}", ""],
+            )
+            .unwrap(),
+        );
+        let out_file = NamedTempFile::new().unwrap().into_temp_path();
+        deck.write_to_file(out_file.to_str().unwrap()).unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+        let db_file = NamedTempFile::new().unwrap();
+        std::io::copy(
+            &mut archive.by_name("collection.anki2").unwrap(),
+            &mut db_file.as_file(),
+        )
+        .unwrap();
+        let conn = rusqlite::Connection::open(db_file.path()).unwrap();
+        let mut ords = conn
+            .prepare("SELECT ord FROM cards")
+            .unwrap()
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        ords.sort_unstable();
+        assert_eq!(ords, vec![0, 1]);
+    }
+
     #[test]
     fn build_all_fields() {
         // A simple test to make sure we can call all the setters on the builder.
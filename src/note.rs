@@ -1,29 +1,72 @@
-use crate::card::Card;
+use crate::card::{Card, CardFlag, Revlog, SchedulingState};
 use crate::error::database_error;
 use crate::model::{Model, ModelType};
-use crate::util::guid_for;
+use crate::util::{
+    decode_entities, field_checksum, guid_for, random_guid, strip_html_media, IdGen,
+};
 use crate::Error;
 use fancy_regex::Regex;
 use rusqlite::{params, Transaction};
-use std::collections::HashSet;
-use std::ops::RangeFrom;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Deref;
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// Tag Anki uses to mark notes
+const MARKED_TAG: &str = "marked";
+
+/// How the GUID of a note is generated, see [`Note::guid_strategy`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GuidStrategy {
+    /// A random GUID, so every generated note is a new note for Anki
+    Random,
+    /// A hash of all fields, so a note is only updated on import if none of its fields changed
+    #[default]
+    FromFields,
+    /// A hash of the first field, so notes can be updated on import as long as the first field is
+    /// unchanged
+    FromFirstField,
+}
+
+/// The model of a `Note`, either borrowed or shared between notes
+#[derive(Clone)]
+enum ModelRef<'a> {
+    Borrowed(&'a Model),
+    Shared(Arc<Model>),
+}
+
+impl Deref for ModelRef<'_> {
+    type Target = Model;
+
+    fn deref(&self) -> &Model {
+        match self {
+            ModelRef::Borrowed(model) => model,
+            ModelRef::Shared(model) => model,
+        }
+    }
+}
 
 /// Note (Flashcard) to be added to a `Deck`
 #[derive(Clone)]
 pub struct Note<'a> {
-    model: &'a Model,
+    model: ModelRef<'a>,
     fields: Vec<String>,
-    sort_field: bool,
+    sort_field: Option<String>,
     tags: Vec<String>,
     guid: String,
     cards: Vec<Card>,
+    created: Option<i64>,
+    modified: Option<i64>,
+    data: Option<serde_json::Value>,
 }
 
 impl<'a> Note<'a> {
     /// Creates a new Note with a new `model` and `fields`
     ///
-    /// Returns `Err` if the fields are not matching the model or if the fields are invalid
+    /// The fields can be anything implementing `ToString`, e.g. string literals or owned `String`s
+    /// built with `format!`. The note keeps its own copy, so they do not have to outlive it.
+    ///
+    /// Returns `Err` if the number of fields does not match the model or if the fields are invalid
     ///
     /// Example:
     /// ```
@@ -31,9 +74,58 @@ impl<'a> Note<'a> {
     ///
     /// let model = basic_model();
     /// let note = Note::new(&model, vec!["What is the capital of France?", "Paris"]);
+    ///
+    /// let (country, capital) = ("Spain", "Madrid");
+    /// let note = Note::new(
+    ///     &model,
+    ///     [format!("What is the capital of {}?", country), capital.to_string()],
+    /// );
+    /// ```
+    pub fn new(
+        model: &'a Model,
+        fields: impl IntoIterator<Item = impl ToString>,
+    ) -> Result<Self, Error> {
+        Self::with_model(ModelRef::Borrowed(model), fields)
+    }
+
+    /// Creates a new Note with a shared `model` and `fields`
+    ///
+    /// Unlike [`Note::new`], the note does not borrow the model, so neither the note nor the
+    /// `Deck` and `Package` it is added to are tied to the lifetime of the model. This is the
+    /// recommended pattern if models and decks are kept in separate long-lived collections, e.g.
+    /// when generating a lot of notes for shared models. Cloning the `Arc` is cheap, so the model
+    /// itself is never copied.
+    ///
+    /// Returns `Err` if the number of fields does not match the model or if the fields are invalid
+    ///
+    /// Example:
     /// ```
-    pub fn new(model: &'a Model, fields: Vec<impl ToString>) -> Result<Self, Error> {
-        let fields = fields.iter().map(|s| s.to_string()).collect();
+    /// use std::sync::Arc;
+    /// use genanki_rs::{Deck, Model, Note, basic_model};
+    ///
+    /// fn deck_for(model: &Arc<Model>) -> Deck<'static> {
+    ///     let mut deck = Deck::new(1234, "Geography", "");
+    ///     let note = Note::new_shared(Arc::clone(model), vec!["Capital of France", "Paris"]);
+    ///     deck.add_note(note.unwrap());
+    ///     deck
+    /// }
+    ///
+    /// let model = Arc::new(basic_model());
+    /// let deck = deck_for(&model);
+    /// ```
+    pub fn new_shared(
+        model: Arc<Model>,
+        fields: impl IntoIterator<Item = impl ToString>,
+    ) -> Result<Self, Error> {
+        Self::with_model(ModelRef::Shared(model), fields)
+    }
+
+    fn with_model(
+        model: ModelRef<'a>,
+        fields: impl IntoIterator<Item = impl ToString>,
+    ) -> Result<Self, Error> {
+        let fields: Vec<String> = fields.into_iter().map(|s| s.to_string()).collect();
+        check_number_model_fields_matches_num_fields(&model, &fields)?;
         let cards = match model.get_model_type() {
             ModelType::FrontBack => front_back_cards(&model, &fields)?,
             ModelType::Cloze => cloze_cards(&model, &fields),
@@ -42,70 +134,103 @@ impl<'a> Note<'a> {
         Ok(Self {
             model,
             fields,
-            sort_field: false,
+            sort_field: None,
             tags: vec![],
             guid,
             cards,
+            created: None,
+            modified: None,
+            data: None,
         })
     }
 
     /// Creates a new Note with a new `model`, `fields` and custom parameters:
-    /// * `sort_field` - whether to sort field, default is `false`
+    /// * `sort_field` - Custom sort field value, default is the field at the model's
+    ///   `sort_field_index`
     /// * `tags` - List of tags
-    /// * `guid` - Custom unique note id, default is hash of all fields
+    /// * `guid` - Custom unique note id, default is [`guid_for`] of all fields
     ///
-    /// Returns `Err` if tags or fields are invalid
+    /// Returns `Err` if tags or fields are invalid, or if the number of fields does not match the
+    /// model
     pub fn new_with_options(
         model: &'a Model,
-        fields: Vec<impl ToString>,
-        sort_field: Option<bool>,
+        fields: impl IntoIterator<Item = impl ToString>,
+        sort_field: Option<&str>,
         tags: Option<Vec<impl ToString>>,
         guid: Option<&str>,
     ) -> Result<Self, Error> {
-        let tags = tags
-            .unwrap_or_default()
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
+        let tags = dedup_tags(
+            tags.unwrap_or_default()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        );
         validate_tags(&tags)?;
-        let fields = fields.iter().map(|s| s.to_string()).collect();
-        let cards = match model.get_model_type() {
-            ModelType::FrontBack => front_back_cards(&model, &fields)?,
-            ModelType::Cloze => cloze_cards(&model, &fields),
-        };
-        let guid = guid.unwrap_or(&guid_for(&fields)).to_string();
+        let note = Self::with_model(ModelRef::Borrowed(model), fields)?;
         Ok(Self {
-            model,
-            fields,
-            sort_field: sort_field.unwrap_or(false),
+            sort_field: sort_field.map(str::to_string),
             tags,
-            guid,
-            cards,
+            guid: guid.map_or(note.guid.clone(), str::to_string),
+            ..note
         })
     }
 
-    /// Returns a new Note with the sort field replace with the new one
-    pub fn sort_field(self, sort_field: bool) -> Self {
-        Self { sort_field, ..self }
+    /// Returns a new Note with the sort field replaced with the new one
+    ///
+    /// The sort field is written to `notes.sfld`, which Anki uses to sort notes in the browser.
+    /// By default it is the value of the field at the model's
+    /// [`sort_field_index`](crate::Model::sort_field_index) (`sortf`) with HTML stripped by
+    /// [`strip_html_media`](crate::strip_html_media), like Anki does. Setting it here overrides
+    /// that value for this note only and is written verbatim, e.g. to sort by a version of the
+    /// field without diacritics. The model's `sortf` is still written unchanged, so Anki
+    /// recomputes `sfld` from the sort field if the note is edited after importing.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Note, basic_model};
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["<b>Éclair</b>", "a pastry"])
+    ///     .unwrap()
+    ///     .sort_field("eclair");
+    /// ```
+    pub fn sort_field(self, sort_field: impl ToString) -> Self {
+        Self {
+            sort_field: Some(sort_field.to_string()),
+            ..self
+        }
     }
 
     /// Sets or replaces tags with the provided ones
+    ///
+    /// Duplicate tags are only kept once. Tags must not contain whitespace, otherwise writing the
+    /// note fails with `Error::TagContainsWhitespace`.
     pub fn tags(self, tags: impl IntoIterator<Item = impl ToString>) -> Self {
         Self {
-            tags: tags.into_iter().map(|tag| tag.to_string()).collect(),
+            tags: dedup_tags(tags.into_iter().map(|tag| tag.to_string()).collect()),
             ..self
         }
     }
 
     /// Adds an additional tag
+    ///
+    /// Adding a tag that is already present has no effect.
     pub fn with_tag(mut self, tag: impl ToString) -> Self {
-        self.tags.push(tag.to_string());
+        let tag = tag.to_string();
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
         self
     }
 
     /// Sets the GUID for this note
     ///
-    /// The GUID is auto-generated if this option is not provided.
+    /// The GUID is auto-generated from the note's fields if this option is not provided.
+    ///
+    /// Anki identifies notes by their GUID when importing: a note whose GUID already exists in
+    /// the collection updates the existing note instead of being added as a duplicate. Setting a
+    /// deterministic GUID (e.g. derived from a primary key in your data) therefore allows
+    /// regenerating a deck and re-importing it without losing review progress.
     pub fn guid(self, guid: impl ToString) -> Self {
         Self {
             guid: guid.to_string(),
@@ -113,8 +238,290 @@ impl<'a> Note<'a> {
         }
     }
 
-    pub(super) fn model(&self) -> Model {
-        self.model.clone()
+    /// Replaces the GUID of this note with one generated by `strategy`
+    ///
+    /// The default is [`GuidStrategy::FromFields`]. A GUID set with [`Note::guid`] before is
+    /// overwritten.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{basic_model, GuidStrategy, Note};
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+    ///     .unwrap()
+    ///     .guid_strategy(GuidStrategy::FromFirstField);
+    /// ```
+    pub fn guid_strategy(self, strategy: GuidStrategy) -> Self {
+        let guid = match strategy {
+            GuidStrategy::Random => random_guid(),
+            GuidStrategy::FromFields => guid_for(&self.fields),
+            GuidStrategy::FromFirstField => guid_for(&self.fields[..self.fields.len().min(1)]),
+        };
+        Self { guid, ..self }
+    }
+
+    /// Sets the `due` value of all cards of this note
+    ///
+    /// For new cards this is the position in the new card queue, so cards with a lower `due`
+    /// are shown first. Defaults to `0`.
+    pub fn due(mut self, due: i64) -> Self {
+        self.set_due(due);
+        self
+    }
+
+    /// Sets the `due` value of the card generated from the template with ordinal `ord`
+    ///
+    /// This overrides the value set by [`Note::due`] for a single card. Has no effect if the
+    /// note has no card with this ordinal.
+    pub fn card_due(mut self, ord: i64, due: i64) -> Self {
+        for card in self.cards.iter_mut().filter(|card| card.ord == ord) {
+            card.due = due;
+        }
+        self
+    }
+
+    /// Sets the scheduling state of the card generated from the template with ordinal `ord`
+    ///
+    /// This imports the card as already studied, e.g. when migrating study history from another
+    /// app. The `due` value set with [`Note::card_due`] must match the queue of the state, e.g. the
+    /// number of days since the collection was created for review cards (see
+    /// [`Package::creation_time`](crate::Package::creation_time)). Suspended notes stay
+    /// suspended regardless of the queue. Has no effect if the note has no card with this
+    /// ordinal.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{basic_model, CardQueue, CardType, Note, SchedulingState};
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["Capital of France", "Paris"])
+    ///     .unwrap()
+    ///     .card_scheduling(
+    ///         0,
+    ///         SchedulingState {
+    ///             card_type: CardType::Review,
+    ///             queue: CardQueue::Review,
+    ///             ivl: 21,
+    ///             factor: 2500,
+    ///             reps: 6,
+    ///             lapses: 0,
+    ///             ..SchedulingState::default()
+    ///         },
+    ///     )
+    ///     .card_due(0, 3000);
+    /// ```
+    pub fn card_scheduling(mut self, ord: i64, scheduling: SchedulingState) -> Self {
+        for card in self.cards.iter_mut().filter(|card| card.ord == ord) {
+            card.scheduling = scheduling;
+        }
+        self
+    }
+
+    /// Adds an entry to the review log of the card generated from the template with ordinal `ord`
+    ///
+    /// This is part of the review history API for migrating study history from other apps,
+    /// together with [`Note::card_scheduling`]: the entries are written to the `revlog` table and
+    /// linked to the card, so Anki's statistics include the past reviews. If another entry already
+    /// has the id of `review`, the next free id is used. Has no effect if the note has no card with
+    /// this ordinal.
+    pub fn add_review(mut self, ord: i64, review: Revlog) -> Self {
+        for card in self.cards.iter_mut().filter(|card| card.ord == ord) {
+            card.reviews.push(review);
+        }
+        self
+    }
+
+    /// Sets the time the note and its cards were created, in seconds since the unix epoch
+    ///
+    /// Anki derives the creation date of notes and cards from their ids, so this sets the ids of
+    /// the note and its cards. If another note or card already has the id, the next free id is
+    /// used. Defaults to the timestamp the package is written with.
+    pub fn created(self, created: i64) -> Self {
+        Self {
+            created: Some(created),
+            ..self
+        }
+    }
+
+    /// Sets the time the note and its cards were last modified, in seconds since the unix epoch
+    ///
+    /// Defaults to the timestamp the package is written with.
+    pub fn modified(self, modified: i64) -> Self {
+        Self {
+            modified: Some(modified),
+            ..self
+        }
+    }
+
+    /// Sets the JSON written to the `data` column of the note
+    ///
+    /// Anki keeps data of some note features there which have no column of their own. Most notes
+    /// leave it empty, which is the default, so only set it if you know the format Anki expects.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Note, basic_model};
+    /// use serde_json::json;
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["Question", "Answer"])
+    ///     .unwrap()
+    ///     .data(json!({"frozen": [0]}));
+    /// ```
+    pub fn data(self, data: serde_json::Value) -> Self {
+        Self {
+            data: Some(data),
+            ..self
+        }
+    }
+
+    /// Flags the card generated from the template with ordinal `ord` with `flag`
+    ///
+    /// Has no effect if the note has no card with this ordinal.
+    pub fn flag(mut self, ord: i64, flag: CardFlag) -> Self {
+        for card in self.cards.iter_mut().filter(|card| card.ord == ord) {
+            card.flag = flag;
+        }
+        self
+    }
+
+    /// Marks the note, which Anki stores as the `marked` tag
+    ///
+    /// Anki compares tags case-insensitively, so a note which already has e.g. a `Marked` tag is
+    /// left unchanged.
+    pub fn marked(self) -> Self {
+        if self.is_marked() {
+            self
+        } else {
+            self.with_tag(MARKED_TAG)
+        }
+    }
+
+    /// Returns whether the note is marked, see [`Note::marked`]
+    pub fn is_marked(&self) -> bool {
+        self.tags
+            .iter()
+            .any(|tag| tag.eq_ignore_ascii_case(MARKED_TAG))
+    }
+
+    /// Returns the filenames of media files referenced in the fields of this note
+    ///
+    /// Both `[sound:filename]` and `src` attributes of `<img>`, `<audio>`, `<video>` and `<source>`
+    /// tags are found, with the `src` attribute in double, single or no quotes. Filenames are not
+    /// checked against a list of known extensions, so any format Anki can play is returned. Each
+    /// filename is only returned once, in the order of its first reference. HTML entities like
+    /// `&amp;` are decoded like Anki does. This can be used to collect the media files for a
+    /// `Package`.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Note, basic_model};
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["[sound:hello.mp3]", "<img src='hello.jpg'>"]).unwrap();
+    /// assert_eq!(note.media_references(), vec!["hello.mp3", "hello.jpg"]);
+    /// ```
+    pub fn media_references(&self) -> Vec<String> {
+        static MEDIA_REFERENCE: OnceLock<Regex> = OnceLock::new();
+        let media_reference = MEDIA_REFERENCE.get_or_init(|| {
+            Regex::new(
+                r#"\[sound:(.+?)\]|(?i:<(?:img|audio|video|source)\b[^>]*?\bsrc\s*=\s*)(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+            )
+            .expect("static regex")
+        });
+        let mut references: Vec<String> = vec![];
+        for field in &self.fields {
+            for reference in re_findall(media_reference, field) {
+                let reference = decode_entities(&reference);
+                if !references.contains(&reference) {
+                    references.push(reference);
+                }
+            }
+        }
+        references
+    }
+
+    /// Only keeps the cards generated from the templates with the ordinals `ords`
+    ///
+    /// By default a card is generated for every template whose front side is not empty. This
+    /// suppresses the other cards of this note, e.g. the reverse card of a single note of a
+    /// "Basic (and reversed card)" model. Templates with an empty front side still do not generate
+    /// a card, even if their ordinal is listed. For cloze models, the ordinal of `{{c1::...}}` is
+    /// `0`.
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{basic_and_reversed_card_model, Note};
+    ///
+    /// let model = basic_and_reversed_card_model();
+    /// let note = Note::new(&model, vec!["der Hund", "the dog"])
+    ///     .unwrap()
+    ///     .enabled_cards(&[0]);
+    /// ```
+    pub fn enabled_cards(mut self, ords: &[i64]) -> Self {
+        self.cards.retain(|card| ords.contains(&card.ord));
+        self
+    }
+
+    /// Puts the card generated from the template with ordinal `ord` into the deck with `deck_id`
+    ///
+    /// By default all cards of a note are put into the deck the note is added to. This allows e.g.
+    /// keeping reverse cards in a separate deck. The deck must be part of the same `Package`,
+    /// otherwise writing fails with `Error::MissingDeck`. Has no effect if the note has no card
+    /// with this ordinal.
+    pub fn card_deck(mut self, ord: i64, deck_id: i64) -> Self {
+        for card in self.cards.iter_mut().filter(|card| card.ord == ord) {
+            card.deck_id = Some(deck_id);
+        }
+        self
+    }
+
+    /// Returns the ids of the decks the cards of this note are put into instead of their `Deck`
+    pub(super) fn card_deck_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.cards.iter().flat_map(|card| {
+            let original_deck_id = Some(card.scheduling.odid).filter(|&odid| odid != 0);
+            card.deck_id.into_iter().chain(original_deck_id)
+        })
+    }
+
+    pub(super) fn model_default_deck_id(&self) -> Option<i64> {
+        self.model.default_deck_id()
+    }
+
+    pub(super) fn card_dues(&self) -> impl Iterator<Item = i64> + '_ {
+        self.cards.iter().map(|card| card.due)
+    }
+
+    pub(super) fn set_due(&mut self, due: i64) {
+        for card in &mut self.cards {
+            card.due = due;
+        }
+    }
+
+    /// Returns the fields of the note, in the order of the model's fields
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Note, basic_model};
+    ///
+    /// let model = basic_model();
+    /// let note = Note::new(&model, vec!["Capital of France", "Paris"]).unwrap();
+    /// assert_eq!(note.fields(), ["Capital of France", "Paris"]);
+    /// assert_eq!(note.model_id(), model.id);
+    /// ```
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Returns the id of the note's model
+    pub fn model_id(&self) -> i64 {
+        self.model.id
+    }
+
+    /// Returns the note's model
+    pub fn model(&self) -> &Model {
+        &self.model
     }
 
     #[allow(dead_code)]
@@ -122,19 +529,12 @@ impl<'a> Note<'a> {
         self.cards.clone()
     }
 
-    fn get_guid(&self) -> String {
-        self.guid.clone()
+    pub(super) fn has_cards(&self) -> bool {
+        !self.cards.is_empty()
     }
 
-    fn check_number_model_fields_matches_num_fields(&self) -> Result<(), Error> {
-        if self.model.fields().len() != self.fields.len() {
-            Err(Error::ModelFieldCountMismatch(
-                self.model.fields().len(),
-                self.fields.len(),
-            ))
-        } else {
-            Ok(())
-        }
+    pub(super) fn get_guid(&self) -> String {
+        self.guid.clone()
     }
 
     fn check_invalid_html_tags_in_fields(&self) -> Result<(), Error> {
@@ -150,11 +550,28 @@ impl<'a> Note<'a> {
         Ok(())
     }
 
+    fn sort_field_value(&self) -> String {
+        match &self.sort_field {
+            Some(sort_field) => sort_field.clone(),
+            None => strip_html_media(self.model.sort_field_of(&self.fields)),
+        }
+    }
+
+    fn checksum(&self) -> i64 {
+        self.fields.first().map_or(0, |field| field_checksum(field))
+    }
+
     fn format_fields(&self) -> String {
         self.fields.clone().join("\x1f")
     }
 
-    fn format_tags(&self) -> String {
+    fn format_data(&self) -> String {
+        self.data
+            .as_ref()
+            .map_or_else(String::new, serde_json::Value::to_string)
+    }
+
+    pub(super) fn format_tags(&self) -> String {
         format!(" {} ", self.tags.join(" "))
     }
     pub(super) fn write_to_db(
@@ -162,44 +579,70 @@ impl<'a> Note<'a> {
         transaction: &Transaction,
         timestamp: f64,
         deck_id: i64,
-        mut id_gen: &mut RangeFrom<usize>,
+        id_gen: &mut IdGen,
     ) -> Result<(), Error> {
-        self.check_number_model_fields_matches_num_fields()?;
         self.check_invalid_html_tags_in_fields()?;
+        validate_tags(&self.tags)?;
+        let created = self.created.map(|created| created * 1000);
+        let note_id = match created {
+            Some(created) => id_gen.unused_id(transaction, "notes", created)?,
+            None => id_gen.next_unused_id(transaction, "notes")?,
+        };
+        let modified = self.modified.unwrap_or(timestamp as i64);
+        // The statement is cached on the connection, so it is only prepared once per package
         transaction
-            .execute(
-                "INSERT INTO notes VALUES(?,?,?,?,?,?,?,?,?,?,?);",
-                params![
-                    id_gen.next(),        // id
-                    self.get_guid(),      // guid
-                    self.model.id,        // mid
-                    timestamp as i64,     // mod
-                    -1,                   // usn
-                    self.format_tags(),   // TODO tags
-                    self.format_fields(), // flds
-                    self.sort_field,      // sfld
-                    0,                    // csum, can be ignored
-                    0,                    // flags
-                    "",                   // data
-                ],
-            )
+            .prepare_cached("INSERT INTO notes VALUES(?,?,?,?,?,?,?,?,?,?,?);")
+            .map_err(database_error)?
+            .execute(params![
+                note_id,                 // id
+                self.get_guid(),         // guid
+                self.model.id,           // mid
+                modified,                // mod
+                -1,                      // usn
+                self.format_tags(),      // tags
+                self.format_fields(),    // flds
+                self.sort_field_value(), // sfld
+                self.checksum(),         // csum
+                0,                       // flags
+                self.format_data(),      // data
+            ])
             .map_err(database_error)?;
-        let note_id = transaction.last_insert_rowid() as usize;
         for card in &self.cards {
-            card.write_to_db(transaction, timestamp, deck_id, note_id, &mut id_gen)?
+            card.write_to_db(transaction, modified, deck_id, note_id, created, id_gen)?
         }
         Ok(())
     }
 }
 
-fn cloze_cards(model: &Model, self_fields: &Vec<String>) -> Vec<Card> {
-    let mut card_ords: HashSet<i64> = HashSet::new();
+fn check_number_model_fields_matches_num_fields(
+    model: &Model,
+    fields: &[String],
+) -> Result<(), Error> {
+    if model.fields().len() != fields.len() {
+        Err(Error::FieldCountMismatch {
+            model: model.name().to_string(),
+            expected: model.fields().len(),
+            got: fields.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn cloze_cards(model: &Model, self_fields: &[String]) -> Vec<Card> {
+    let mut card_ords: BTreeSet<i64> = BTreeSet::new();
+    static CLOZE_FIELD: OnceLock<Regex> = OnceLock::new();
+    static LEGACY_CLOZE_FIELD: OnceLock<Regex> = OnceLock::new();
+    static CLOZE_DELETION: OnceLock<Regex> = OnceLock::new();
+    let cloze_field = CLOZE_FIELD
+        .get_or_init(|| Regex::new(r"{{[^}]*?cloze:(?:[^}]?:)*(.+?)}}").expect("static regex"));
+    let legacy_cloze_field =
+        LEGACY_CLOZE_FIELD.get_or_init(|| Regex::new("<%cloze:(.+?)%>").expect("static regex"));
+    let cloze_deletion =
+        CLOZE_DELETION.get_or_init(|| Regex::new(r"(?s){{c(\d+)::.+?}}").expect("static regex"));
     let mut cloze_replacements: HashSet<String> = HashSet::new();
-    cloze_replacements.extend(re_findall(
-        r"{{[^}]*?cloze:(?:[^}]?:)*(.+?)}}",
-        &model.templates()[0].qfmt,
-    ));
-    cloze_replacements.extend(re_findall("<%cloze:(.+?)%>", &model.templates()[0].qfmt));
+    cloze_replacements.extend(re_findall(cloze_field, &model.templates()[0].qfmt));
+    cloze_replacements.extend(re_findall(legacy_cloze_field, &model.templates()[0].qfmt));
     for field_name in cloze_replacements {
         let fields = model.fields();
         let mut field_index_iter = fields
@@ -212,7 +655,7 @@ fn cloze_cards(model: &Model, self_fields: &Vec<String>) -> Vec<Card> {
         } else {
             "".to_string()
         };
-        let updates_str = re_findall(r"(?s){{c(\d+)::.+?}}", &field_value);
+        let updates_str = re_findall(cloze_deletion, &field_value);
         let updates = updates_str
             .iter()
             .map(|m| i64::from_str(m).expect("parsed from regex") - 1)
@@ -228,40 +671,38 @@ fn cloze_cards(model: &Model, self_fields: &Vec<String>) -> Vec<Card> {
         .collect()
 }
 
-fn front_back_cards(model: &Model, self_fields: &Vec<String>) -> Result<Vec<Card>, Error> {
-    let mut rv = vec![];
-    for (card_ord, any_or_all, required_field_ords) in model.req()?.drain(..) {
-        let mut iter = required_field_ords.iter().map(|&ord| &self_fields[ord]);
-        let condition = match any_or_all.as_str() {
-            "any" => iter.any(|field| field.len() > 0),
-            "all" => iter.all(|field| field.len() > 0),
-            _ => panic!("only any or all"),
-        };
-        if condition {
-            rv.push(Card::new(card_ord as i64, false));
-        }
-    }
-    Ok(rv)
+fn front_back_cards(model: &Model, self_fields: &[String]) -> Result<Vec<Card>, Error> {
+    Ok(model
+        .non_empty_front_ords(self_fields)?
+        .into_iter()
+        .map(|ord| Card::new(ord, false))
+        .collect())
 }
 
-fn re_findall(regex_str: &'static str, to_match: &str) -> Vec<String> {
-    let regex = Regex::new(regex_str).expect("static regex");
+/// Returns the text of all groups which took part in a match of `regex` in `to_match`
+fn re_findall(regex: &Regex, to_match: &str) -> Vec<String> {
     regex
         .captures_iter(to_match)
         .filter_map(|m| m.ok())
-        .map(|cap| {
+        .flat_map(|cap| {
             cap.iter()
                 .skip(1)
-                .filter_map(|m| m)
+                .flatten()
                 .map(|m| m.as_str().to_string())
                 .collect::<Vec<String>>()
         })
-        .flatten()
         .collect()
 }
 
-fn validate_tags(tags: &Vec<String>) -> Result<(), Error> {
-    if tags.iter().any(|tag| tag.contains(' ')) {
+fn dedup_tags(tags: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    tags.into_iter()
+        .filter(|tag| seen.insert(tag.clone()))
+        .collect()
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), Error> {
+    if tags.iter().any(|tag| tag.contains(char::is_whitespace)) {
         Err(Error::TagContainsWhitespace)
     } else {
         Ok(())
@@ -281,20 +722,28 @@ mod tests {
     use super::*;
     use crate::apkg_col::APKG_COL;
     use crate::apkg_schema::APKG_SCHEMA;
-    use crate::{Field, Model, Note, Template};
+    use crate::{
+        basic_and_reversed_card_model, basic_model, CardQueue, CardType, Field, Model, Note,
+        ReviewKind, Template,
+    };
     use rusqlite::Connection;
     use std::time::{SystemTime, UNIX_EPOCH};
     use tempfile::{NamedTempFile, TempPath};
 
-    fn write_to_db_setup(db_file: &TempPath) -> (Connection, f64, i64, RangeFrom<usize>) {
-        let conn = Connection::open(&db_file).unwrap();
+    fn write_to_db_setup(db_file: &TempPath) -> (Connection, f64, i64, IdGen) {
+        let conn = Connection::open(db_file).unwrap();
         conn.execute_batch(APKG_SCHEMA).unwrap();
         conn.execute_batch(APKG_COL).unwrap();
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs_f64();
-        (conn, timestamp, 0, ((timestamp * 1000.0) as usize..))
+        (
+            conn,
+            timestamp,
+            0,
+            IdGen::new((timestamp * 1000.0) as usize),
+        )
     }
 
     #[test]
@@ -317,6 +766,587 @@ mod tests {
         transaction.commit().unwrap();
     }
 
+    #[test]
+    fn data_written_to_db() {
+        let model = basic_model();
+        let read_data = |note: Note| {
+            let db_file = NamedTempFile::new().unwrap().into_temp_path();
+            let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+            let transaction = conn.transaction().unwrap();
+            note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+                .unwrap();
+            transaction
+                .query_row("SELECT data FROM notes", [], |row| row.get::<_, String>(0))
+                .unwrap()
+        };
+        let note = Note::new(&model, vec!["a", "b"]).unwrap();
+        assert_eq!(read_data(note.clone()), "");
+        let note = note.data(serde_json::json!({"frozen": [0]}));
+        assert_eq!(read_data(note), r#"{"frozen":[0]}"#);
+    }
+
+    #[test]
+    fn guid_written_to_db() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt(r#"{{FrontSide}}<hr id="answer">{{Answer}}"#)],
+        );
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+            .unwrap()
+            .guid("my-primary-key");
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let guid: String = transaction
+            .query_row("SELECT guid FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(guid, "my-primary-key");
+    }
+
+    #[test]
+    fn owned_fields() {
+        let model = basic_model();
+        let fields = (1..=2).map(|i| format!("field {}", i));
+        let note = Note::new(&model, fields).unwrap();
+        assert_eq!(note.fields(), ["field 1", "field 2"]);
+        let note =
+            Note::new_shared(Arc::new(model), vec![String::from("a"), 1.to_string()]).unwrap();
+        assert_eq!(note.fields(), ["a", "1"]);
+    }
+
+    #[test]
+    fn default_guid_matches_genanki() {
+        let model = basic_model();
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"]).unwrap();
+        assert_eq!(note.get_guid(), "HSnG{z%dU<");
+    }
+
+    #[test]
+    fn guid_strategies() {
+        let model = basic_model();
+        let note = |answer| Note::new(&model, vec!["Capital of Argentina", answer]).unwrap();
+        assert_eq!(
+            note("Buenos Aires")
+                .guid_strategy(GuidStrategy::FromFields)
+                .get_guid(),
+            note("Buenos Aires").get_guid()
+        );
+        assert_ne!(
+            note("Buenos Aires")
+                .guid_strategy(GuidStrategy::FromFields)
+                .get_guid(),
+            note("BA")
+                .guid_strategy(GuidStrategy::FromFields)
+                .get_guid()
+        );
+        assert_eq!(
+            note("Buenos Aires")
+                .guid_strategy(GuidStrategy::FromFirstField)
+                .get_guid(),
+            note("BA")
+                .guid_strategy(GuidStrategy::FromFirstField)
+                .get_guid()
+        );
+        assert_ne!(
+            note("Buenos Aires")
+                .guid_strategy(GuidStrategy::Random)
+                .get_guid(),
+            note("Buenos Aires")
+                .guid_strategy(GuidStrategy::Random)
+                .get_guid()
+        );
+    }
+
+    #[test]
+    fn due_written_to_db() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Question}}")
+                    .afmt("{{Answer}}"),
+                Template::new("Card 2")
+                    .qfmt("{{Answer}}")
+                    .afmt("{{Question}}"),
+                Template::new("Card 3")
+                    .qfmt("{{Question}}{{Answer}}")
+                    .afmt("-"),
+            ],
+        );
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+            .unwrap()
+            .due(42)
+            .card_due(1, 7);
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let dues = transaction
+            .prepare("SELECT ord, due FROM cards ORDER BY ord")
+            .unwrap()
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(dues, vec![(0, 42), (1, 7), (2, 42)]);
+    }
+
+    #[test]
+    fn optional_reverse_card() {
+        let model = Model::new(
+            1,
+            "Basic (optional reversed card)",
+            vec![
+                Field::new("Front"),
+                Field::new("Back"),
+                Field::new("Add Reverse"),
+            ],
+            vec![
+                Template::new("Card 1").qfmt("{{Front}}").afmt("{{Back}}"),
+                Template::new("Card 2")
+                    .qfmt("{{#Add Reverse}}{{Back}}{{/Add Reverse}}")
+                    .afmt("{{Front}}"),
+            ],
+        );
+        let card_ords = |fields: Vec<&str>| {
+            Note::new(&model, fields)
+                .unwrap()
+                .cards
+                .iter()
+                .map(|card| card.ord)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(card_ords(vec!["Front", "Back", ""]), vec![0]);
+        assert_eq!(card_ords(vec!["Front", "Back", "y"]), vec![0, 1]);
+        assert_eq!(card_ords(vec!["", "Back", "y"]), vec![1]);
+        assert_eq!(card_ords(vec!["Front", "", "y"]), vec![0]);
+    }
+
+    #[test]
+    fn hint_field_generates_card() {
+        let model = Model::new(
+            1,
+            "hint model",
+            vec![
+                Field::new("Question"),
+                Field::new("Hint").sticky(true),
+                Field::new("Answer"),
+            ],
+            vec![Template::new("Card 1")
+                .qfmt("{{hint:Hint}}")
+                .afmt("{{FrontSide}}<hr id=answer>{{Question}} {{Answer}}")],
+        );
+        let note = Note::new(&model, vec!["", "starts with P", "Paris"]).unwrap();
+        assert_eq!(
+            note.cards.iter().map(|card| card.ord).collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert!(Note::new(&model, vec!["Capital?", "", "Paris"])
+            .unwrap()
+            .cards
+            .is_empty());
+        let entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(entry.tmpls[0].qfmt, "{{hint:Hint}}");
+        assert!(entry.flds[1].sticky);
+    }
+
+    #[test]
+    fn enabled_cards() {
+        let card_ords = |note: Note| note.cards.iter().map(|card| card.ord).collect::<Vec<_>>();
+        let model = basic_and_reversed_card_model();
+        let note = Note::new(&model, vec!["der Hund", "the dog"]).unwrap();
+        assert_eq!(card_ords(note.clone()), vec![0, 1]);
+        assert_eq!(card_ords(note.clone().enabled_cards(&[1])), vec![1]);
+        assert!(card_ords(note.enabled_cards(&[])).is_empty());
+
+        let model = crate::cloze_model();
+        let note = Note::new(&model, vec!["{{c1::Paris}} is in {{c3::France}}"]).unwrap();
+        assert_eq!(card_ords(note.enabled_cards(&[1, 2])), vec![2]);
+    }
+
+    #[test]
+    fn static_text_does_not_generate_card() {
+        let model = Model::new(
+            1,
+            "static text",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![
+                Template::new("Card 1").qfmt("{{Front}}").afmt("{{Back}}"),
+                Template::new("Card 2")
+                    .qfmt("What is {{^Front}}missing{{/Front}} {{hint:Back}}?")
+                    .afmt("{{Front}}"),
+            ],
+        );
+        let note = Note::new(&model, vec!["Front", ""]).unwrap();
+        assert_eq!(note.cards.len(), 1);
+        let note = Note::new(&model, vec!["", "Back"]).unwrap();
+        assert_eq!(
+            note.cards.iter().map(|card| card.ord).collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn sort_field_written_to_db() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt("{{Answer}}")],
+        )
+        .sort_field_index(1);
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"]).unwrap();
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let sfld: String = transaction
+            .query_row("SELECT sfld FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sfld, "Buenos Aires");
+        let csum: i64 = transaction
+            .query_row("SELECT csum FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(csum, field_checksum("Capital of Argentina"));
+    }
+
+    #[test]
+    fn sort_field_html_stripped() {
+        let model = basic_model();
+        let note = Note::new(
+            &model,
+            vec!["<b>Caf&eacute;</b><img src=\"cafe.jpg\">", "coffee"],
+        )
+        .unwrap();
+        assert_eq!(note.sort_field_value(), "Café cafe.jpg ");
+    }
+
+    #[test]
+    fn sort_field_overrides_model_sort_field_index() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt("{{Answer}}")],
+        )
+        .sort_field_index(1);
+        let note = Note::new(&model, vec!["Capital of Argentina", "<b>Buenos Aires</b>"])
+            .unwrap()
+            .sort_field("buenos aires");
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let sfld: String = transaction
+            .query_row("SELECT sfld FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sfld, "buenos aires");
+    }
+
+    #[test]
+    fn shared_model_outlives_scope() {
+        let deck = {
+            let model = Arc::new(Model::new(
+                1376484377,
+                "Simple Model",
+                vec![Field::new("Question"), Field::new("Answer")],
+                vec![Template::new("Card 1")
+                    .qfmt("{{Question}}")
+                    .afmt("{{Answer}}")],
+            ));
+            let mut deck = crate::Deck::new(1234, "Example Deck", "");
+            for i in 0..3 {
+                deck.add_note(
+                    Note::new_shared(Arc::clone(&model), vec![format!("Q{}", i), "A".to_string()])
+                        .unwrap(),
+                );
+            }
+            deck
+        };
+        let mut package = crate::Package::new(vec![deck], vec![]).unwrap();
+        let (conn, _db_file) = crate::package::tests::write_and_open_collection(&mut package);
+        let mids = conn
+            .prepare("SELECT DISTINCT mid FROM notes")
+            .unwrap()
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(mids, vec![1376484377]);
+    }
+
+    #[test]
+    fn media_references_found() {
+        let model = Model::new(
+            1,
+            "media model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![Template::new("Card 1").qfmt("{{Front}}").afmt("{{Back}}")],
+        );
+        let note = Note::new(
+            &model,
+            vec![
+                r#"[sound:a b.mp3] <img class="x" src="one.jpg"> <IMG src='two.png'/>"#,
+                "<img src=three.gif> [sound:a b.mp3] <img alt=src src=\"one.jpg\">",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            note.media_references(),
+            vec!["a b.mp3", "one.jpg", "two.png", "three.gif"]
+        );
+    }
+
+    #[test]
+    fn media_references_with_audio_and_video_extensions() {
+        let model = basic_model();
+        for extension in ["ogg", "oga", "webm", "mp4", "wav", "mp3", "flac", "opus"] {
+            let sound = format!("[sound:word.{}]", extension);
+            let video = format!(
+                r#"<video controls><source src="clip.{0}" type="video/{0}"></video>"#,
+                extension
+            );
+            let note = Note::new(&model, vec![sound.as_str(), video.as_str()]).unwrap();
+            assert_eq!(
+                note.media_references(),
+                vec![format!("word.{}", extension), format!("clip.{}", extension)]
+            );
+        }
+        let note = Note::new(&model, vec!["<audio src='a.oga'>", "<VIDEO SRC=b.webm>"]).unwrap();
+        assert_eq!(note.media_references(), vec!["a.oga", "b.webm"]);
+    }
+
+    #[test]
+    fn created_and_modified_written_to_db() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Question}}")
+                    .afmt("{{Answer}}"),
+                Template::new("Card 2")
+                    .qfmt("{{Answer}}")
+                    .afmt("{{Question}}"),
+            ],
+        );
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        for question in ["first", "second"].iter() {
+            Note::new(&model, vec![*question, "answer"])
+                .unwrap()
+                .created(1_500_000_000)
+                .modified(1_500_000_100)
+                .write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+                .unwrap();
+        }
+        Note::new(&model, vec!["third", "answer"])
+            .unwrap()
+            .write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let ids_and_mods = |table: &str| {
+            transaction
+                .prepare(&format!("SELECT id, mod FROM {} ORDER BY id", table))
+                .unwrap()
+                .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let notes = ids_and_mods("notes");
+        assert_eq!(
+            notes[..2],
+            [
+                (1_500_000_000_000, 1_500_000_100),
+                (1_500_000_000_001, 1_500_000_100)
+            ]
+        );
+        assert_eq!(notes[2].1, timestamp as i64);
+        let cards = ids_and_mods("cards");
+        assert_eq!(
+            cards[..4]
+                .iter()
+                .map(|&(id, _)| id - 1_500_000_000_000)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(cards.len(), 6);
+    }
+
+    #[test]
+    fn flags_and_marked_written_to_db() {
+        let model = Model::new(
+            1376484377,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Question}}")
+                    .afmt("{{Answer}}"),
+                Template::new("Card 2")
+                    .qfmt("{{Answer}}")
+                    .afmt("{{Question}}"),
+            ],
+        );
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+            .unwrap()
+            .flag(1, CardFlag::Orange)
+            .marked();
+        assert!(note.is_marked());
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let flags = transaction
+            .prepare("SELECT flags FROM cards ORDER BY ord")
+            .unwrap()
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(flags, vec![0, 2]);
+        let tags: String = transaction
+            .query_row("SELECT tags FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, " marked ");
+
+        let note = Note::new(&model, vec!["Capital of Chile", "Santiago"])
+            .unwrap()
+            .with_tag("Marked")
+            .marked();
+        assert!(note.is_marked());
+        assert_eq!(note.format_tags(), " Marked ");
+    }
+
+    #[test]
+    fn card_scheduling_written_to_db() {
+        let model = basic_and_reversed_card_model();
+        let review = SchedulingState {
+            card_type: CardType::Review,
+            queue: CardQueue::Review,
+            ivl: 21,
+            factor: 2500,
+            reps: 6,
+            lapses: 1,
+            odue: 120,
+            odid: 5678,
+        };
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+            .unwrap()
+            .card_scheduling(1, review)
+            .card_due(1, 3000);
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let cards = transaction
+            .prepare(
+                "SELECT type, queue, due, ivl, factor, reps, lapses, odue, odid \
+                 FROM cards ORDER BY ord",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                (0..9)
+                    .map(|i| row.get::<_, i64>(i))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            cards,
+            vec![
+                vec![0, 0, 0, 0, 0, 0, 0, 0, 0],
+                vec![2, 2, 3000, 21, 2500, 6, 1, 120, 5678]
+            ]
+        );
+    }
+
+    #[test]
+    fn reviews_written_to_db() {
+        let model = basic_and_reversed_card_model();
+        let review = Revlog {
+            id: 1_500_000_000_000,
+            ease: 3,
+            ivl: 4,
+            last_ivl: 1,
+            factor: 2500,
+            time: 6_500,
+            review_kind: ReviewKind::Review,
+        };
+        let note = Note::new(&model, vec!["Capital of Argentina", "Buenos Aires"])
+            .unwrap()
+            .add_review(1, review)
+            .add_review(
+                1,
+                Revlog {
+                    ease: 1,
+                    review_kind: ReviewKind::Learning,
+                    ..review
+                },
+            );
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+            .unwrap();
+        let reviewed_card: i64 = transaction
+            .query_row("SELECT id FROM cards WHERE ord = 1", [], |row| row.get(0))
+            .unwrap();
+        let revlog = transaction
+            .prepare(
+                "SELECT id, cid, ease, ivl, lastIvl, factor, time, type FROM revlog ORDER BY id",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                (0..8)
+                    .map(|i| row.get::<_, i64>(i))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            revlog,
+            vec![
+                vec![1_500_000_000_000, reviewed_card, 3, 4, 1, 2500, 6500, 1],
+                vec![1_500_000_000_001, reviewed_card, 1, 4, 1, 2500, 6500, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let model = basic_model();
+        let note = Note::new(&model, vec!["Capital of France", "Paris"]).unwrap();
+        assert_eq!(note.fields(), ["Capital of France", "Paris"]);
+        assert_eq!(note.model_id(), model.id);
+        assert!(std::ptr::eq(note.model(), &model));
+
+        let shared = Arc::new(basic_model());
+        let note = Note::new_shared(Arc::clone(&shared), vec!["a", "b"]).unwrap();
+        assert!(std::ptr::eq(note.model(), &*shared));
+    }
+
     #[test]
     fn tags_new() {
         let _ = Note::new_with_options(
@@ -342,6 +1372,32 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn tags_deduplicated() {
+        let model = Model::new(0, "test", vec![], vec![]);
+        let note = Note::new(&model, Vec::<String>::new())
+            .unwrap()
+            .tags(["foo", "bar", "foo"])
+            .with_tag("bar")
+            .with_tag("baz");
+        assert_eq!(note.format_tags(), " foo bar baz ");
+    }
+
+    #[test]
+    fn tags_builder_whitespace_rejected() {
+        let model = Model::new(0, "test", vec![], vec![]);
+        let note = Note::new(&model, Vec::<String>::new())
+            .unwrap()
+            .with_tag("fo\to");
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+        let transaction = conn.transaction().unwrap();
+        assert!(matches!(
+            note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen),
+            Err(Error::TagContainsWhitespace)
+        ));
+    }
+
     #[test]
     fn num_fields_equals_model_ok() {
         let model = Model::new(
@@ -428,6 +1484,44 @@ mod tests {
         transaction.commit().unwrap();
     }
 
+    #[test]
+    fn num_fields_mismatch_error() {
+        let model = Model::new(
+            1894808898,
+            "Test Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt(r#"{{FrontSide}}<hr id="answer">{{Answer}}"#)],
+        );
+        assert!(matches!(
+            Note::new(&model, vec!["Capital of Germany"]),
+            Err(Error::FieldCountMismatch { model, expected: 2, got: 1 }) if model == "Test Model"
+        ));
+        assert!(matches!(
+            Note::new(&model, vec!["Capital of Germany", "Berlin", "Extra"]),
+            Err(Error::FieldCountMismatch {
+                expected: 2,
+                got: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Note::new_with_options(
+                &model,
+                vec!["Capital of Germany"],
+                None,
+                None::<Vec<&str>>,
+                None
+            ),
+            Err(Error::FieldCountMismatch {
+                expected: 2,
+                got: 1,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn find_invalid_html_tags_in_field_ok() {
         assert_eq!(
@@ -524,7 +1618,7 @@ mod tests {
             .guid("1234")
             .tags(["tag_a"])
             .with_tag("tag_b")
-            .sort_field(true);
+            .sort_field("a");
 
         Ok(())
     }
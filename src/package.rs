@@ -1,19 +1,30 @@
 use rusqlite::{Connection, Transaction};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tempfile::NamedTempFile;
-use zip::{write::FileOptions, ZipWriter};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{Seek, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use crate::apkg_col::APKG_COL;
 use crate::apkg_schema::APKG_SCHEMA;
+use crate::db_entries::ModelDbEntry;
 use crate::deck::Deck;
 use crate::error::{database_error, json_error, zip_error};
+use crate::media::{MediaFile, MediaSource};
+use crate::model::Model;
+use crate::reader::ImportedPackage;
+use crate::util::IdGen;
 use crate::Error;
-use std::str::FromStr;
+
+/// Id of the `Default` deck every collection contains
+pub(crate) const DEFAULT_DECK_ID: i64 = 1;
+
+/// Hour of the day at which Anki starts a new day unless the rollover is set
+const DEFAULT_ROLLOVER: i64 = 4;
 
 /// `Package` to pack `Deck`s and `media_files` and write them to a `.apkg` file
 ///
@@ -41,21 +52,775 @@ use std::str::FromStr;
 /// let mut package = Package::new(vec![deck], vec![/*"sound.mp3", "images/image.jpg"*/]).unwrap();
 /// package.write_to_file("output.apkg").unwrap();
 /// ```
+///
+/// ## Package format
+///
+/// Packages are written in the legacy `.apkg` format, which every Anki version can import. The
+/// zip archive contains:
+/// * `collection.anki2` (or `collection.anki21`, see [`Package::schema_version`]) - the sqlite
+///   collection with the notes, cards, decks and models
+/// * `media` - a JSON object mapping the name of each media entry to its filename, e.g.
+///   `{"0": "sound.mp3", "1": "image.jpg"}`
+/// * `0`, `1`, ... - one entry per media file with its contents
+///
+/// Anki copies each media entry into its flat media folder under the mapped filename, so
+/// filenames must not contain path separators. The newer format with a zstd compressed media
+/// manifest and sha1 checksums, which Anki uses for its own exports since 2.1.50, is not written.
 pub struct Package<'a> {
     decks: Vec<Deck<'a>>,
-    media_files: Vec<PathBuf>,
+    media_files: Vec<MediaFile>,
+    schema_version: SchemaVersion,
+    db_compression: Compression,
+    media_compression: Compression,
+    dedup_media: bool,
+    parallel: bool,
+    dual_schema: bool,
+    strict: bool,
+    temp_dir: Option<PathBuf>,
+    creation_time: Option<i64>,
+    id_seed: Option<i64>,
+    collection_conf: BTreeMap<String, serde_json::Value>,
+    connection_hooks: Vec<ConnectionHook<'a>>,
+    on_progress: Option<ProgressCallback<'a>>,
+    skipped_notes: Vec<String>,
+}
+
+/// Builder for a [`Package`], see [`Package::builder`]
+#[derive(Default)]
+pub struct PackageBuilder<'a> {
+    decks: Vec<Deck<'a>>,
+    media_files: Vec<MediaFile>,
+}
+
+impl<'a> PackageBuilder<'a> {
+    /// Adds a `deck` to the package
+    pub fn deck(mut self, deck: Deck<'a>) -> Self {
+        self.decks.push(deck);
+        self
+    }
+
+    /// Adds the media file at `path` to the package, named after its basename
+    pub fn media_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.media_files
+            .push(MediaFile::from(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Adds an in-memory media file with the given `filename` to the package
+    pub fn media_bytes(mut self, filename: &str, data: Vec<u8>) -> Self {
+        self.media_files
+            .push(MediaFile::new(filename, MediaSource::Bytes(data)));
+        self
+    }
+
+    /// Adds `media_file` to the package under its logical name
+    pub fn media(mut self, media_file: MediaFile) -> Self {
+        self.media_files.push(media_file);
+        self
+    }
+
+    /// Creates the package
+    ///
+    /// Returns `Err` if the name of a media file is invalid, e.g. if a path does not end in a
+    /// filename or points to something other than a regular file, or an in-memory filename
+    /// contains a path separator
+    pub fn build(self) -> Result<Package<'a>, Error> {
+        for media_file in &self.media_files {
+            media_file.validate()?;
+        }
+        let mut package = Package::new_with_bytes(self.decks, vec![]);
+        package.media_files = self.media_files;
+        Ok(package)
+    }
+}
+
+/// Custom code run on the collection before it is written, see [`Package::with_connection`]
+type ConnectionHook<'a> = Box<dyn Fn(&Transaction) -> Result<(), Error> + Send + Sync + 'a>;
+
+/// Callback reporting the progress of writing a package, see [`Package::on_progress`]
+type ProgressCallback<'a> = Box<dyn FnMut(Progress) + Send + Sync + 'a>;
+
+/// Stage of writing a package, see [`Package::on_progress`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Progress {
+    /// The decks, notes and cards are being written into the collection
+    WritingCollection,
+    /// `written` of the `total` media files have been written into the package
+    WritingMedia { written: usize, total: usize },
+}
+
+/// Version of the collection written into a `Package`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// `collection.anki2` using the v1 scheduler, which every Anki version can import
+    #[default]
+    V1,
+    /// `collection.anki21` using the v2 scheduler, which Anki 2.1 imports without upgrading
+    V2,
+}
+
+/// Compression used for the files inside a `Package`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Store files without compression, which is fastest for already compressed media
+    Stored,
+    /// Deflate files using the default compression level
+    #[default]
+    Deflated,
+    /// Deflate files using a level between `0` (fastest) and `9` (smallest)
+    DeflatedLevel(u8),
+}
+
+impl Compression {
+    /// Options for zip entries compressed with this method and modified at `modified`
+    fn file_options(self, modified: DateTime) -> FileOptions {
+        let options = FileOptions::default().last_modified_time(modified);
+        match self {
+            Compression::Stored => options.compression_method(CompressionMethod::Stored),
+            Compression::Deflated => options.compression_method(CompressionMethod::Deflated),
+            Compression::DeflatedLevel(level) => options
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(Some(level.min(9).into())),
+        }
+    }
+}
+
+impl SchemaVersion {
+    fn collection_name(self) -> &'static str {
+        match self {
+            SchemaVersion::V1 => "collection.anki2",
+            SchemaVersion::V2 => "collection.anki21",
+        }
+    }
+
+    fn scheduler_version(self) -> i64 {
+        match self {
+            SchemaVersion::V1 => 1,
+            SchemaVersion::V2 => 2,
+        }
+    }
 }
 
 impl<'a> Package<'a> {
     /// Create a new package with `decks` and `media_files`
     ///
-    /// Returns `Err` if `media_files` are invalid
+    /// Returns `Err` if `media_files` are invalid, e.g. if a path does not end in a filename or
+    /// points to something other than a regular file
     pub fn new(decks: Vec<Deck<'a>>, media_files: Vec<&str>) -> Result<Self, Error> {
+        Self::new_with_media(
+            decks,
+            media_files.into_iter().map(MediaFile::from).collect(),
+        )
+    }
+
+    /// Create a new package with `decks` and `media_files` named by their logical names
+    ///
+    /// Unlike [`Package::new`], the name Anki sees does not have to be the basename of the file
+    /// on disk.
+    ///
+    /// This is a separate constructor rather than a generic `media_files` parameter of
+    /// [`Package::new`], since the item type of an empty `vec![]` could not be inferred and the
+    /// common `Package::new(decks, vec![])` would no longer compile.
+    ///
+    /// Returns `Err` if `media_files` are invalid, e.g. if a logical name is empty or contains a
+    /// path separator, or if a path points to something other than a regular file
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{MediaFile, MediaSource, Package};
+    /// use std::path::PathBuf;
+    ///
+    /// let package = Package::new_with_media(
+    ///     vec![],
+    ///     vec![
+    ///         MediaFile::from("sounds/goodbye.mp3"),
+    ///         MediaFile::new("hello.mp3", MediaSource::Path(PathBuf::from("tmp/abc123.mp3"))),
+    ///     ],
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn new_with_media(
+        decks: Vec<Deck<'a>>,
+        media_files: Vec<MediaFile>,
+    ) -> Result<Self, Error> {
+        for media_file in &media_files {
+            media_file.validate()?;
+        }
+        Ok(Self {
+            decks,
+            media_files,
+            schema_version: SchemaVersion::default(),
+            db_compression: Compression::default(),
+            media_compression: Compression::default(),
+            dedup_media: false,
+            parallel: false,
+            dual_schema: false,
+            strict: false,
+            temp_dir: None,
+            creation_time: None,
+            id_seed: None,
+            collection_conf: BTreeMap::new(),
+            connection_hooks: vec![],
+            on_progress: None,
+            skipped_notes: vec![],
+        })
+    }
+
+    /// Create a new package with `decks` and no media files
+    ///
+    /// Unlike [`Package::new`] this cannot fail, as there are no media files to validate.
+    pub fn from_decks(decks: Vec<Deck<'a>>) -> Self {
+        Self::new_with_bytes(decks, vec![])
+    }
+
+    /// Create a new package with `decks` and in-memory `media_files`
+    ///
+    /// Each media file is given as a `(filename, data)` pair. The `filename` is the name Anki
+    /// will see, so it is what fields should reference, e.g. `[sound:filename]`.
+    pub fn new_with_bytes(decks: Vec<Deck<'a>>, media_files: Vec<(&str, Vec<u8>)>) -> Self {
         let media_files = media_files
+            .into_iter()
+            .map(|(name, data)| MediaFile::new(name, MediaSource::Bytes(data)))
+            .collect();
+        Self {
+            decks,
+            media_files,
+            schema_version: SchemaVersion::default(),
+            db_compression: Compression::default(),
+            media_compression: Compression::default(),
+            dedup_media: false,
+            parallel: false,
+            dual_schema: false,
+            strict: false,
+            temp_dir: None,
+            creation_time: None,
+            id_seed: None,
+            collection_conf: BTreeMap::new(),
+            connection_hooks: vec![],
+            on_progress: None,
+            skipped_notes: vec![],
+        }
+    }
+
+    /// Returns a builder to create a package step by step
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Deck, Package};
+    ///
+    /// let package = Package::builder()
+    ///     .deck(Deck::new(1234, "Example Deck", ""))
+    ///     .media_bytes("hello.mp3", vec![0xff, 0xe3, 0x18, 0xc4])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder() -> PackageBuilder<'a> {
+        PackageBuilder::default()
+    }
+
+    /// Sets the version of the collection written into the package
+    ///
+    /// Defaults to [`SchemaVersion::V1`] for compatibility with old Anki versions. Use
+    /// [`SchemaVersion::V2`] to avoid the scheduler upgrade prompt of newer Anki versions.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Package, SchemaVersion};
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .schema_version(SchemaVersion::V2);
+    /// ```
+    pub fn schema_version(mut self, schema_version: SchemaVersion) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+
+    /// Sets the compression used for the collection and media files in the package
+    ///
+    /// Defaults to [`Compression::Deflated`]. [`Compression::Stored`] speeds up writing packages
+    /// with a lot of already compressed media like images or audio. Use
+    /// [`Package::db_compression`] and [`Package::media_compression`] to set them separately.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Compression, Package};
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .compression(Compression::Stored);
+    /// ```
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.db_compression = compression;
+        self.media_compression = compression;
+        self
+    }
+
+    /// Sets the compression used for the collection and the media map in the package
+    ///
+    /// Defaults to [`Compression::Deflated`], which shrinks the collection a lot.
+    pub fn db_compression(mut self, compression: Compression) -> Self {
+        self.db_compression = compression;
+        self
+    }
+
+    /// Sets the compression used for the media files in the package
+    ///
+    /// Defaults to [`Compression::Deflated`]. Media like jpg images or mp3 audio is already
+    /// compressed, so [`Compression::Stored`] avoids wasting time on it.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Compression, Package};
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .db_compression(Compression::DeflatedLevel(9))
+    ///     .media_compression(Compression::Stored);
+    /// ```
+    pub fn media_compression(mut self, compression: Compression) -> Self {
+        self.media_compression = compression;
+        self
+    }
+
+    /// Sets whether duplicate media files are only stored once, default is `false`
+    ///
+    /// Media files are duplicates if they have the same filename and their contents have the same
+    /// sha1 hash, e.g. if the same file was added under two different paths. Files with identical
+    /// contents but different filenames are still stored separately, since every entry of the
+    /// package's media map belongs to exactly one filename.
+    ///
+    /// Anki can only keep one file per filename, so writing fails with
+    /// [`Error::MediaNameConflict`] if two media files have the same filename but different
+    /// contents, regardless of this setting.
+    pub fn dedup_media(mut self, dedup_media: bool) -> Self {
+        self.dedup_media = dedup_media;
+        self
+    }
+
+    /// Sets whether media files are compressed on multiple threads, default is `false`
+    ///
+    /// The media files are split into one chunk per available CPU, each chunk is compressed into
+    /// memory on its own thread and the compressed entries are then copied into the package in
+    /// order. This speeds up writing packages with a lot of media, but all compressed media is held
+    /// in memory at once. `cargo bench --bench parallel_media` compares both modes on 1,000 media
+    /// files.
+    ///
+    /// The threads are scoped threads of the standard library rather than a thread pool like
+    /// rayon, so parallel writing needs no extra dependency and is not behind a feature flag.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let package = Package::new(vec![], vec![]).unwrap().parallel(true);
+    /// ```
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Sets whether both `collection.anki2` and `collection.anki21` are written, default is `false`
+    ///
+    /// The two collections contain the same decks, notes and cards, but `collection.anki2` uses the
+    /// v1 scheduler and `collection.anki21` the v2 scheduler, see [`SchemaVersion`]. Old Anki
+    /// versions import the former and Anki 2.1 the latter, so this maximizes compatibility at the
+    /// cost of a larger package. The media files are shared. Overrides
+    /// [`Package::schema_version`].
+    pub fn dual_schema(mut self, dual_schema: bool) -> Self {
+        self.dual_schema = dual_schema;
+        self
+    }
+
+    /// Sets whether writing a package without any notes fails, default is `false`
+    ///
+    /// In strict mode, writing returns [`Error::EmptyPackage`] if none of the decks contain notes.
+    /// This catches e.g. generation loops which never added anything. It also returns
+    /// [`Error::NoCardsGenerated`] for notes which generate no cards because the front sides of
+    /// all templates are empty. Without strict mode these notes are skipped, as Anki cannot import
+    /// them, and their guids are returned by [`Package::skipped_notes`].
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Deck, Error, Package};
+    ///
+    /// let mut package = Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![])
+    ///     .unwrap()
+    ///     .strict(true);
+    /// assert!(matches!(package.write_to_vec(), Err(Error::EmptyPackage)));
+    /// ```
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the first id given to notes and cards, default is the write timestamp in milliseconds
+    ///
+    /// Notes and cards without a [`Note::created`] time get consecutive ids starting at the write
+    /// timestamp, so two packages written in the same millisecond, or with the same explicit
+    /// timestamp, use the same ids. That is fine for separate files, but when their collections
+    /// are merged, e.g. by importing both into Anki, notes of one package can replace notes of
+    /// the other. Seeding each package with its own `start` keeps the id ranges disjoint as long
+    /// as they are further apart than the number of notes and cards. Negative seeds are treated
+    /// as `0`.
+    ///
+    /// Anki derives the creation date of notes and cards from their ids in milliseconds, so the
+    /// seed should still be a plausible timestamp.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let first = Package::new(vec![], vec![]).unwrap().id_seed(1_600_000_000_000);
+    /// let second = Package::new(vec![], vec![]).unwrap().id_seed(1_600_000_100_000);
+    /// ```
+    ///
+    /// [`Note::created`]: crate::Note::created
+    pub fn id_seed(mut self, start: i64) -> Self {
+        self.id_seed = Some(start);
+        self
+    }
+
+    /// Sets the directory the collection is built in before it is compressed into the package
+    ///
+    /// By default a temporary file in the system's temporary directory (e.g. `/tmp`) is used, which
+    /// may be too small for large collections.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .temp_dir(std::env::current_dir().unwrap());
+    /// ```
+    pub fn temp_dir(mut self, temp_dir: impl AsRef<Path>) -> Self {
+        self.temp_dir = Some(temp_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the entry `key` of the collection config to `value`
+    ///
+    /// The collection config is the `conf` JSON object of the `col` table and holds collection
+    /// preferences like `sortType`, `collapseTime` or `curDeck`. Custom entries are merged into the
+    /// default config, so keys which are not set are kept and setting a key again replaces its
+    /// value.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .collection_conf("collapseTime", 600.into());
+    /// ```
+    pub fn collection_conf(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.collection_conf.insert(key.to_string(), value);
+        self
+    }
+
+    /// Sets the creation time of the collection (`col.crt`), in seconds since the unix epoch
+    ///
+    /// Anki counts days, e.g. the due days of review cards, from the creation time. Defaults to the
+    /// start of the day the package is written, i.e. the timestamp rounded down to the last
+    /// [`Package::rollover`] (`4` o'clock unless set) in the timezone of
+    /// [`Package::creation_offset`] (UTC unless set).
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// // 2020-09-13 04:00 UTC, so a review card due on day 10 is due on 2020-09-23
+    /// let package = Package::new(vec![], vec![]).unwrap().creation_time(1_599_969_600);
+    /// ```
+    pub fn creation_time(mut self, creation_time: i64) -> Self {
+        self.creation_time = Some(creation_time);
+        self
+    }
+
+    /// Sets the hour of the day (`0` to `23`) at which Anki starts a new day
+    ///
+    /// This is stored as `rollover` in the collection config and used by the v2 scheduler to reset
+    /// e.g. the new cards per day. Hours above `23` are treated as `23`.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Package, SchemaVersion};
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .schema_version(SchemaVersion::V2)
+    ///     .rollover(6)
+    ///     .creation_offset(-60);
+    /// ```
+    pub fn rollover(self, hour: u8) -> Self {
+        self.collection_conf("rollover", hour.min(23).into())
+    }
+
+    /// Sets the timezone of the collection's creation time, in minutes west of UTC
+    ///
+    /// This is stored as `creationOffset` in the collection config, e.g. `-60` for UTC+1. Together
+    /// with [`Package::rollover`] it determines the local time at which a new day starts.
+    pub fn creation_offset(self, minutes_west: i32) -> Self {
+        self.collection_conf("creationOffset", minutes_west.into())
+    }
+
+    /// Runs `hook` on the collection every time the package is written
+    ///
+    /// The hook is called after the schema, collection config, decks, models, notes and cards have
+    /// been written, but before the transaction is committed. This allows running custom SQL, e.g.
+    /// to add review log entries or to change the collection config. Returning `Err` from the hook
+    /// aborts writing the package. Multiple hooks are run in the order they were added. The
+    /// transaction is a [`rusqlite::Transaction`], which is re-exported as `genanki_rs::rusqlite`.
+    ///
+    /// **Warning:** the collection is not validated afterwards, so misusing this hook can create
+    /// packages which Anki refuses to import or which corrupt the collection they are imported
+    /// into.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Error, Package};
+    ///
+    /// let package = Package::new(vec![], vec![])
+    ///     .unwrap()
+    ///     .with_connection(|transaction| {
+    ///         transaction
+    ///             .execute("UPDATE col SET tags = '{\"imported\": 0}'", [])
+    ///             .map_err(|e| Error::Database(Box::new(e)))?;
+    ///         Ok(())
+    ///     });
+    /// ```
+    pub fn with_connection<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Transaction) -> Result<(), Error> + Send + Sync + 'a,
+    {
+        self.connection_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Calls `on_progress` with the current stage while the package is written
+    ///
+    /// [`Progress::WritingCollection`] is reported once before the collection is written, then
+    /// [`Progress::WritingMedia`] before the first and after each media file. With
+    /// [`Package::parallel`], a media file counts as written once a worker thread has compressed
+    /// it, and the callback is called on the writing thread while the workers run.
+    /// Replaces a previously set callback.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Package, Progress};
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap().on_progress(|progress| {
+    ///     if let Progress::WritingMedia { written, total } = progress {
+    ///         println!("{}/{} media files", written, total);
+    ///     }
+    /// });
+    /// package.write_to_vec().unwrap();
+    /// ```
+    pub fn on_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: FnMut(Progress) + Send + Sync + 'a,
+    {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Adds a `deck` to the package
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Deck, Package};
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap();
+    /// package.add_deck(Deck::new(1234, "Example Deck", ""));
+    /// ```
+    pub fn add_deck(&mut self, deck: Deck<'a>) {
+        self.decks.push(deck);
+    }
+
+    /// Adds the decks and media files of `other` to this package
+    ///
+    /// A deck of `other` with the id of a deck of this package is combined with it by appending
+    /// its notes, e.g. to add generated notes to a deck read with [`Package::read_from_file`].
+    /// Its intro note (see [`Deck::intro_note`]) stays the intro note unless the deck of this
+    /// package already has one, then it is appended like the other notes. Models are written once per id, so notes of both packages can share a model. A media file
+    /// with the same filename and contents as one of this package is only kept once. The settings
+    /// of this package are kept, those of `other` are dropped.
+    ///
+    /// Returns [`Error::DuplicateId`] if two decks with the same id have different names, or
+    /// [`Error::MediaNameConflict`] if two media files with the same filename have different
+    /// contents. The package is not changed then. Two different models with the same id are only
+    /// detected when writing, which fails with [`Error::ModelIdConflict`].
+    ///
+    /// Example:
+    /// ```rust,no_run
+    /// use genanki_rs::{basic_model, Deck, Note, Package};
+    ///
+    /// let model = basic_model();
+    /// let imported = Package::read_from_file("existing.apkg").unwrap();
+    /// let mut package = imported.to_package().unwrap();
+    /// let mut deck = Deck::new(1234, "Example Deck", "");
+    /// deck.add_note(Note::new(&model, vec!["Capital of Italy", "Rome"]).unwrap());
+    /// package.merge(Package::from_decks(vec![deck])).unwrap();
+    /// package.write_to_file("output.apkg").unwrap();
+    /// ```
+    pub fn merge(&mut self, other: Package<'a>) -> Result<(), Error> {
+        for deck in &other.decks {
+            if let Some(existing) = self
+                .decks
+                .iter()
+                .find(|existing| existing.id() == deck.id())
+            {
+                if existing.name() != deck.name() {
+                    return Err(Error::DuplicateId(deck.id()));
+                }
+            }
+        }
+        let mut existing_media = BTreeMap::new();
+        for media_file in &self.media_files {
+            existing_media.insert(
+                media_file.filename()?.to_string(),
+                media_file.source.content_hash()?,
+            );
+        }
+        let mut new_media = vec![];
+        for media_file in other.media_files {
+            let filename = media_file.filename()?.to_string();
+            let content_hash = media_file.source.content_hash()?;
+            match existing_media.get(&filename) {
+                Some(existing_hash) if *existing_hash == content_hash => continue,
+                Some(_) => return Err(Error::MediaNameConflict(filename)),
+                None => {
+                    existing_media.insert(filename, content_hash);
+                    new_media.push(media_file);
+                }
+            }
+        }
+
+        for deck in other.decks {
+            match self
+                .decks
+                .iter_mut()
+                .find(|existing| existing.id() == deck.id())
+            {
+                Some(existing) => existing.append(deck),
+                None => self.decks.push(deck),
+            }
+        }
+        self.media_files.extend(new_media);
+        Ok(())
+    }
+
+    /// Returns the decks of the package, e.g. to add notes after creating the package
+    pub fn decks_mut(&mut self) -> &mut [Deck<'a>] {
+        &mut self.decks
+    }
+
+    /// Returns the number of notes in all decks of the package
+    pub fn total_notes(&self) -> usize {
+        self.decks.iter().map(Deck::note_count).sum()
+    }
+
+    /// Returns the guids of the notes skipped by the last write because they generate no cards,
+    /// see [`Package::strict`]
+    pub fn skipped_notes(&self) -> &[String] {
+        &self.skipped_notes
+    }
+
+    /// Adds an in-memory media file with the given `filename` to the package
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap();
+    /// package.add_media_bytes("hello.mp3", vec![0xff, 0xe3, 0x18, 0xc4]);
+    /// ```
+    pub fn add_media_bytes(&mut self, filename: &str, data: Vec<u8>) {
+        self.media_files
+            .push(MediaFile::new(filename, MediaSource::Bytes(data)));
+    }
+
+    /// Returns the number of media files added to the package
+    pub fn media_count(&self) -> usize {
+        self.media_files.len()
+    }
+
+    /// Returns the total size in bytes of all media files of the package
+    ///
+    /// This is the uncompressed size, files which are deduplicated with [`Package::dedup_media`]
+    /// are counted every time they were added.
+    ///
+    /// Returns `Err` if a media file on disk cannot be accessed
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap();
+    /// package.add_media_bytes("hello.mp3", vec![0xff, 0xe3, 0x18, 0xc4]);
+    /// assert_eq!(package.media_count(), 1);
+    /// assert_eq!(package.media_total_size().unwrap(), 4);
+    /// ```
+    pub fn media_total_size(&self) -> Result<u64, Error> {
+        self.media_files
+            .iter()
+            .map(|media_file| media_file.source.size())
+            .sum()
+    }
+
+    /// Checks that all media files on disk exist and can be read
+    ///
+    /// This is done automatically before a package is written, but calling it directly gives fast
+    /// feedback before e.g. generating a large amount of notes.
+    ///
+    /// Returns [`Error::NotAFile`] if a path points to something other than a regular file, or
+    /// [`Error::MissingMedia`] with the paths of all missing or unreadable files
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{Error, Package};
+    ///
+    /// let package = Package::new(vec![], vec!["does-not-exist.mp3"]).unwrap();
+    /// assert!(matches!(package.verify_media(), Err(Error::MissingMedia(_))));
+    /// ```
+    pub fn verify_media(&self) -> Result<(), Error> {
+        for media_file in &self.media_files {
+            media_file.validate()?;
+        }
+        let missing = self
+            .media_files
             .iter()
-            .map(|&s| PathBuf::from_str(s))
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self { decks, media_files })
+            .filter_map(|media_file| media_file.source.unreadable_path())
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingMedia(missing))
+        }
+    }
+
+    /// Reads an existing `.apkg` file, e.g. one exported from Anki
+    ///
+    /// Both the legacy `collection.anki2` and the newer `collection.anki21` collections are
+    /// supported. Use [`ImportedPackage::to_package`] to get a `Package` that can be modified and
+    /// written again.
+    ///
+    /// Returns `Err` if the file cannot be read or is not a valid package
+    pub fn read_from_file(file: impl AsRef<Path>) -> Result<ImportedPackage, Error> {
+        Self::read_from(File::open(file)?)
+    }
+
+    /// Reads an existing package from a reader
+    ///
+    /// Returns `Err` if an IO error occurrs or the data is not a valid package
+    pub fn read_from<R>(reader: R) -> Result<ImportedPackage, Error>
+    where
+        R: Read + Seek,
+    {
+        ImportedPackage::read_from(reader)
     }
 
     /// Writes the package to a writer
@@ -68,15 +833,104 @@ impl<'a> Package<'a> {
         self.write_to_maybe_timestamp(out, None)
     }
 
+    /// Writes the package to a writer which cannot seek, e.g. a network socket
+    ///
+    /// Writing a zip archive requires seeking, so the package is first written to a temporary file
+    /// (see [`Package::temp_dir`]) and then copied to `out`. Writers which can seek, including
+    /// `std::io::Cursor<&mut Vec<u8>>` to append to an existing buffer, should use
+    /// [`Package::write_to`] instead.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap();
+    /// package.write_to_stream(std::io::sink()).unwrap();
+    /// ```
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_to_stream<W: Write>(&mut self, out: W) -> Result<(), Error> {
+        self.write_to_stream_maybe_timestamp(out, None)
+    }
+
+    /// Writes the package to a writer which cannot seek using a timestamp
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_to_stream_timestamp<W: Write>(
+        &mut self,
+        out: W,
+        timestamp: f64,
+    ) -> Result<(), Error> {
+        self.write_to_stream_maybe_timestamp(out, Some(timestamp))
+    }
+
     /// Writes the package to a file
     ///
+    /// `file` can be anything that converts to a path, e.g. `&str`, `String`, `&Path` or `PathBuf`.
+    ///
     /// Returns `Err` if the `file` cannot be created
-    pub fn write_to_file(&mut self, file: &str) -> Result<(), Error> {
+    pub fn write_to_file(&mut self, file: impl AsRef<Path>) -> Result<(), Error> {
         self.write_to_file_maybe_timestamp(file, None)
     }
 
+    /// Writes the package into a new buffer and returns it
+    ///
+    /// This is useful to e.g. serve the package over HTTP without writing it to a file.
+    ///
+    /// The collection itself is still built in a temporary file, see [`Package::temp_dir`]. There
+    /// is no option to keep it in an in-memory database instead, because the bytes of an
+    /// in-memory SQLite database can only be read back by serializing it, which the bundled
+    /// version of `rusqlite` does not support, or by copying it into a file again.
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_to_vec(&mut self) -> Result<Vec<u8>, Error> {
+        let mut out = Cursor::new(Vec::new());
+        self.write_to(&mut out)?;
+        Ok(out.into_inner())
+    }
+
+    /// Writes the package into a new buffer using a timestamp and returns it
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_to_vec_timestamp(&mut self, timestamp: f64) -> Result<Vec<u8>, Error> {
+        let mut out = Cursor::new(Vec::new());
+        self.write_to_timestamp(&mut out, timestamp)?;
+        Ok(out.into_inner())
+    }
+
+    /// Writes the package into an anonymous temporary file and returns it, rewound to the start
+    ///
+    /// Unlike [`Package::write_to_vec`], the package never has to fit into memory, which matters
+    /// for packages with a lot of media, e.g. on a server. The file is created in
+    /// [`Package::temp_dir`] if it is set and is deleted once it is closed.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::Package;
+    /// use std::io::Read;
+    ///
+    /// let mut package = Package::new(vec![], vec![]).unwrap();
+    /// let mut bytes = vec![];
+    /// package.write_spooled().unwrap().read_to_end(&mut bytes).unwrap();
+    /// ```
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_spooled(&mut self) -> Result<File, Error> {
+        self.spool(None)
+    }
+
+    /// Writes the package into an anonymous temporary file using a timestamp and returns it
+    ///
+    /// Returns `Err` if an IO error occurrs
+    pub fn write_spooled_timestamp(&mut self, timestamp: f64) -> Result<File, Error> {
+        self.spool(Some(timestamp))
+    }
+
     /// Writes the package to a writer using a timestamp
     ///
+    /// The timestamp is also used as the modification time of the zip entries, so writing the
+    /// same package with the same timestamp gives identical bytes.
+    ///
     /// Returns `Err` if an IO error occurrs
     pub fn write_to_timestamp<W>(&mut self, out: W, timestamp: f64) -> Result<(), Error>
     where
@@ -88,16 +942,42 @@ impl<'a> Package<'a> {
     /// Writes the package to a file using a timestamp
     ///
     /// Returns `Err` if the `file` cannot be created
-    pub fn write_to_file_timestamp(&mut self, file: &str, timestamp: f64) -> Result<(), Error> {
+    pub fn write_to_file_timestamp(
+        &mut self,
+        file: impl AsRef<Path>,
+        timestamp: f64,
+    ) -> Result<(), Error> {
         self.write_to_file_maybe_timestamp(file, Some(timestamp))
     }
 
+    fn write_to_stream_maybe_timestamp<W: Write>(
+        &mut self,
+        mut out: W,
+        timestamp: Option<f64>,
+    ) -> Result<(), Error> {
+        let mut spooled = self.spool(timestamp)?;
+        std::io::copy(&mut spooled, &mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes the package into an anonymous temporary file and rewinds it
+    fn spool(&mut self, timestamp: Option<f64>) -> Result<File, Error> {
+        let mut spooled = match &self.temp_dir {
+            Some(temp_dir) => tempfile::tempfile_in(temp_dir)?,
+            None => tempfile::tempfile()?,
+        };
+        self.write_to_maybe_timestamp(&mut spooled, timestamp)?;
+        spooled.seek(SeekFrom::Start(0))?;
+        Ok(spooled)
+    }
+
     fn write_to_file_maybe_timestamp(
         &mut self,
-        file: &str,
+        file: impl AsRef<Path>,
         timestamp: Option<f64>,
     ) -> Result<(), Error> {
-        let file = File::create(&file)?;
+        let file = File::create(file)?;
         self.write_to_maybe_timestamp(file, timestamp)?;
         Ok(())
     }
@@ -106,7 +986,31 @@ impl<'a> Package<'a> {
     where
         W: Write + Seek,
     {
-        let db_file = NamedTempFile::new()?.into_temp_path();
+        let mut on_progress = self.on_progress.take();
+        let result = self.write_package(out, timestamp, &mut |progress| {
+            if let Some(on_progress) = &mut on_progress {
+                on_progress(progress);
+            }
+        });
+        self.on_progress = on_progress;
+        result
+    }
+
+    fn write_package<W>(
+        &mut self,
+        out: W,
+        timestamp: Option<f64>,
+        report: &mut dyn FnMut(Progress),
+    ) -> Result<(), Error>
+    where
+        W: Write + Seek,
+    {
+        self.verify_media()?;
+        let db_file = match &self.temp_dir {
+            Some(temp_dir) => NamedTempFile::new_in(temp_dir)?,
+            None => NamedTempFile::new()?,
+        }
+        .into_temp_path();
 
         let mut conn = Connection::open(&db_file).map_err(database_error)?;
         let transaction = conn.transaction().map_err(database_error)?;
@@ -118,66 +1022,1544 @@ impl<'a> Package<'a> {
                 .unwrap_or(0.0)
         });
 
+        report(Progress::WritingCollection);
         self.write_to_db(&transaction, timestamp)?;
         transaction.commit().map_err(database_error)?;
         conn.close().expect("Should always close");
 
+        // Taking the modification time of the entries from the timestamp instead of the current
+        // time keeps packages written with the same timestamp identical
+        let modified = zip_date_time(timestamp);
+        let db_options = self.db_compression.file_options(modified);
+        let media_options = self.media_compression.file_options(modified);
         let mut outzip = ZipWriter::new(out);
-        outzip
-            .start_file("collection.anki2", FileOptions::default())
-            .map_err(zip_error)?;
-        outzip.write_all(&read_file_bytes(db_file)?)?;
+        let schema_versions = if self.dual_schema {
+            vec![SchemaVersion::V1, SchemaVersion::V2]
+        } else {
+            vec![self.schema_version]
+        };
+        for schema_version in schema_versions {
+            if self.dual_schema {
+                let conn = Connection::open(&db_file).map_err(database_error)?;
+                set_scheduler_version(&conn, schema_version)?;
+                conn.close().expect("Should always close");
+            }
+            outzip
+                .start_file(schema_version.collection_name(), db_options)
+                .map_err(zip_error)?;
+            std::io::copy(&mut File::open(&db_file)?, &mut outzip)?;
+        }
 
-        let media_file_idx_to_path = self
-            .media_files
+        let media_files = self.media_files_to_write()?;
+        let media_map = media_files
             .iter()
             .enumerate()
-            .collect::<HashMap<usize, &PathBuf>>();
-        let media_map = media_file_idx_to_path
-            .clone()
-            .into_iter()
-            .map(|(id, path)| {
-                (
-                    id.to_string(),
-                    path.file_name()
-                        .expect("Should always have a filename")
-                        .to_str()
-                        .expect("should always have string"),
-                )
-            })
-            .collect::<HashMap<String, &str>>();
+            .map(|(idx, media_file)| Ok((idx.to_string(), media_file.filename()?)))
+            .collect::<Result<BTreeMap<String, &str>, Error>>()?;
         let media_json = serde_json::to_string(&media_map).map_err(json_error)?;
-        outzip
-            .start_file("media", FileOptions::default())
-            .map_err(zip_error)?;
+        outzip.start_file("media", db_options).map_err(zip_error)?;
         outzip.write_all(media_json.as_bytes())?;
 
-        for (idx, &path) in &media_file_idx_to_path {
-            outzip
-                .start_file(idx.to_string(), FileOptions::default())
-                .map_err(zip_error)?;
-            outzip.write_all(&read_file_bytes(path)?)?;
+        let total = media_files.len();
+        report(Progress::WritingMedia { written: 0, total });
+        let mut written = 0;
+        let mut media_written = || {
+            written += 1;
+            report(Progress::WritingMedia { written, total });
+        };
+        if self.parallel {
+            self.write_media_parallel(
+                &mut outzip,
+                &media_files,
+                media_options,
+                &mut media_written,
+            )?;
+        } else {
+            write_media(
+                &mut outzip,
+                &media_files,
+                0,
+                media_options,
+                &mut media_written,
+            )?;
         }
         outzip.finish().map_err(zip_error)?;
         Ok(())
     }
 
+    /// Compresses chunks of `media_files` on separate threads and copies them into `outzip`
+    fn write_media_parallel<W>(
+        &self,
+        outzip: &mut ZipWriter<W>,
+        media_files: &[&MediaFile],
+        options: FileOptions,
+        media_written: &mut dyn FnMut(),
+    ) -> Result<(), Error>
+    where
+        W: Write + Seek,
+    {
+        if media_files.is_empty() {
+            return Ok(());
+        }
+        let threads = std::thread::available_parallelism().map_or(1, usize::from);
+        let chunk_size = media_files.len().div_ceil(threads);
+        let chunks = std::thread::scope(|scope| {
+            // The workers report each compressed file, so progress is reported while they run
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let handles = media_files
+                .chunks(chunk_size)
+                .enumerate()
+                .map(|(chunk_idx, chunk)| {
+                    let sender = sender.clone();
+                    scope.spawn(move || {
+                        let mut chunk_zip = ZipWriter::new(Cursor::new(Vec::new()));
+                        write_media(
+                            &mut chunk_zip,
+                            chunk,
+                            chunk_idx * chunk_size,
+                            options,
+                            &mut || {
+                                let _ = sender.send(());
+                            },
+                        )?;
+                        Ok(chunk_zip.finish().map_err(zip_error)?.into_inner())
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(sender);
+            for () in receiver {
+                media_written();
+            }
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("media compression thread panicked"))
+                .collect::<Result<Vec<Vec<u8>>, Error>>()
+        })?;
+        for chunk in chunks {
+            let mut chunk_zip = ZipArchive::new(Cursor::new(chunk)).map_err(zip_error)?;
+            for idx in 0..chunk_zip.len() {
+                let file = chunk_zip.by_index_raw(idx).map_err(zip_error)?;
+                outzip.raw_copy_file(file).map_err(zip_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn media_files_to_write(&self) -> Result<Vec<&MediaFile>, Error> {
+        let mut name_counts = HashMap::new();
+        for media_file in &self.media_files {
+            *name_counts.entry(media_file.filename()?).or_insert(0) += 1;
+        }
+        // Only files sharing their filename with another file have to be hashed
+        let mut content_hashes: HashMap<&str, Vec<u8>> = HashMap::new();
+        let mut media_files = vec![];
+        for media_file in &self.media_files {
+            let filename = media_file.filename()?;
+            if name_counts[filename] > 1 {
+                let content_hash = media_file.source.content_hash()?;
+                match content_hashes.get(filename) {
+                    Some(existing_hash) if *existing_hash != content_hash => {
+                        return Err(Error::MediaNameConflict(filename.to_string()))
+                    }
+                    Some(_) if self.dedup_media => continue,
+                    Some(_) => {}
+                    None => {
+                        content_hashes.insert(filename, content_hash);
+                    }
+                }
+            }
+            media_files.push(media_file);
+        }
+        Ok(media_files)
+    }
+
+    /// Checks that all decks cards are put into and all default decks of models are part of the
+    /// package
+    fn check_card_decks(&self) -> Result<(), Error> {
+        let mut deck_ids = self.decks.iter().map(Deck::id).collect::<HashSet<_>>();
+        deck_ids.insert(DEFAULT_DECK_ID);
+        match self
+            .decks
+            .iter()
+            .flat_map(|deck| deck.card_deck_ids().chain(deck.model_default_deck_ids()))
+            .find(|deck_id| !deck_ids.contains(deck_id))
+        {
+            Some(deck_id) => Err(Error::MissingDeck(deck_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that no two decks and no deck and model share an id
+    fn check_ids(&self) -> Result<(), Error> {
+        let mut deck_ids = HashSet::new();
+        if let Some(deck) = self.decks.iter().find(|deck| !deck_ids.insert(deck.id())) {
+            return Err(Error::DuplicateId(deck.id()));
+        }
+        deck_ids.insert(DEFAULT_DECK_ID);
+        match self
+            .decks
+            .iter()
+            .flat_map(Deck::model_ids)
+            .find(|model_id| deck_ids.contains(model_id))
+        {
+            Some(model_id) => Err(Error::DuplicateId(model_id)),
+            None => Ok(()),
+        }
+    }
+
     fn write_to_db(&mut self, transaction: &Transaction, timestamp: f64) -> Result<(), Error> {
-        let mut id_gen = ((timestamp * 1000.0) as usize)..;
+        if self.strict && self.total_notes() == 0 {
+            return Err(Error::EmptyPackage);
+        }
+        self.check_ids()?;
+        self.check_card_decks()?;
+        let first_id = match self.id_seed {
+            Some(start) => start.max(0) as usize,
+            None => (timestamp * 1000.0) as usize,
+        };
+        let mut id_gen = IdGen::new(first_id);
         transaction
             .execute_batch(APKG_SCHEMA)
             .map_err(database_error)?;
         transaction
             .execute_batch(APKG_COL)
             .map_err(database_error)?;
+        // The collection is modified now, in milliseconds unlike the entries inside of it
+        transaction
+            .execute("UPDATE col SET mod = ?", [(timestamp * 1000.0) as i64])
+            .map_err(database_error)?;
+        self.write_conf_to_db(transaction)?;
+        let creation_time = self
+            .creation_time
+            .unwrap_or_else(|| self.start_of_day(timestamp));
+        transaction
+            .execute("UPDATE col SET crt = ?", [creation_time])
+            .map_err(database_error)?;
+        self.write_models_to_db(transaction, timestamp)?;
+        self.skipped_notes.clear();
         for deck in &mut self.decks {
-            deck.write_to_db(&transaction, timestamp, &mut id_gen)?;
+            deck.write_to_db(
+                transaction,
+                timestamp,
+                &mut id_gen,
+                self.strict,
+                &mut self.skipped_notes,
+            )?;
+        }
+        Deck::write_parent_decks_to_db(transaction, timestamp)?;
+        for hook in &self.connection_hooks {
+            hook(transaction)?;
         }
         Ok(())
     }
+
+    /// Writes the models of the notes of all decks into the collection, each only once
+    ///
+    /// A model used by several decks is written with the first of them as its deck. Returns
+    /// [`Error::ModelIdConflict`] if two different models have the same id.
+    fn write_models_to_db(&self, transaction: &Transaction, timestamp: f64) -> Result<(), Error> {
+        let models_json_str: String = transaction
+            .query_row("SELECT models FROM col", [], |row| row.get(0))
+            .map_err(database_error)?;
+        let mut models: BTreeMap<i64, ModelDbEntry> =
+            serde_json::from_str(&models_json_str).map_err(json_error)?;
+        // Notes may each hold their own clone of a model, so the models already compared with the
+        // written entry are remembered and every distinct model is serialized only once
+        let mut checked: BTreeMap<i64, (serde_json::Value, Vec<&Model>)> = BTreeMap::new();
+        for deck in &self.decks {
+            for note in deck.all_notes() {
+                let model = note.model();
+                match checked.get_mut(&model.id) {
+                    Some((_, checked_models))
+                        if checked_models
+                            .iter()
+                            .any(|&checked_model| std::ptr::eq(checked_model, model)) => {}
+                    Some((written_value, checked_models)) => {
+                        let entry = model.to_model_db_entry(timestamp, models[&model.id].did)?;
+                        if serde_json::to_value(entry).map_err(json_error)? != *written_value {
+                            return Err(Error::ModelIdConflict(model.id));
+                        }
+                        checked_models.push(model);
+                    }
+                    None => {
+                        let entry = model.to_model_db_entry(timestamp, deck.id())?;
+                        let value = serde_json::to_value(&entry).map_err(json_error)?;
+                        checked.insert(model.id, (value, vec![model]));
+                        models.insert(model.id, entry);
+                    }
+                }
+            }
+        }
+        transaction
+            .execute(
+                "UPDATE col SET models = ?",
+                [serde_json::to_string(&models).map_err(json_error)?],
+            )
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Returns the last time at or before `timestamp` at which a new day started, using the
+    /// rollover hour and timezone of the collection config
+    fn start_of_day(&self, timestamp: f64) -> i64 {
+        let conf_value = |key: &str, default: i64| {
+            self.collection_conf
+                .get(key)
+                .and_then(serde_json::Value::as_i64)
+                .unwrap_or(default)
+        };
+        let rollover_secs = conf_value("rollover", DEFAULT_ROLLOVER) * 3600;
+        let offset_secs = conf_value("creationOffset", 0) * 60;
+        let since_rollover = (timestamp as i64 - offset_secs - rollover_secs).rem_euclid(86_400);
+        timestamp as i64 - since_rollover
+    }
+
+    /// Merges the scheduler version and custom entries into the collection config
+    fn write_conf_to_db(&self, transaction: &Transaction) -> Result<(), Error> {
+        if self.schema_version == SchemaVersion::V1 && self.collection_conf.is_empty() {
+            return Ok(());
+        }
+        update_conf(transaction, |conf| {
+            if self.schema_version != SchemaVersion::V1 {
+                conf.insert(
+                    "schedVer".to_string(),
+                    self.schema_version.scheduler_version().into(),
+                );
+            }
+            conf.extend(self.collection_conf.clone());
+        })
+    }
+}
+
+/// Sets the scheduler version of the collection config to the one of `schema_version`
+fn set_scheduler_version(conn: &Connection, schema_version: SchemaVersion) -> Result<(), Error> {
+    update_conf(conn, |conf| {
+        conf.insert(
+            "schedVer".to_string(),
+            schema_version.scheduler_version().into(),
+        );
+    })
+}
+
+/// Reads the collection config, changes it with `update` and writes it back
+fn update_conf(
+    conn: &Connection,
+    update: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+) -> Result<(), Error> {
+    let conf_json_str: String = conn
+        .query_row("SELECT conf FROM col", [], |row| row.get(0))
+        .map_err(database_error)?;
+    let mut conf = serde_json::from_str(&conf_json_str).map_err(json_error)?;
+    update(&mut conf);
+    conn.execute(
+        "UPDATE col SET conf = ?",
+        [serde_json::to_string(&conf).map_err(json_error)?],
+    )
+    .map_err(database_error)?;
+    Ok(())
+}
+
+/// Converts `timestamp` in seconds since the unix epoch to the modification time of zip entries
+///
+/// Zip entries store the date and time in UTC with a resolution of two seconds. Times outside of
+/// the years 1980 to 2107, which zip cannot represent, are written as the start of 1980.
+fn zip_date_time(timestamp: f64) -> DateTime {
+    let secs = timestamp.max(0.0) as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since the unix epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    DateTime::from_date_and_time(
+        u16::try_from(year).unwrap_or(u16::MAX),
+        month as u8,
+        day as u8,
+        (secs_of_day / 3600) as u8,
+        (secs_of_day % 3600 / 60) as u8,
+        (secs_of_day % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Writes `media_files` into `outzip` as entries named by their index, starting at `first_idx`
+///
+/// `media_written` is called after each media file.
+fn write_media<W>(
+    outzip: &mut ZipWriter<W>,
+    media_files: &[&MediaFile],
+    first_idx: usize,
+    options: FileOptions,
+    media_written: &mut dyn FnMut(),
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    for (idx, media_file) in media_files.iter().enumerate() {
+        outzip
+            .start_file((first_idx + idx).to_string(), options)
+            .map_err(zip_error)?;
+        media_file.source.write_to(&mut *outzip)?;
+        media_written();
+    }
+    Ok(())
 }
 
-#[inline]
-fn read_file_bytes<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
-    Ok(std::fs::read(path)?)
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{basic_model, Field, Model, Note, Template};
+    use std::io::Read;
+    use tempfile::TempPath;
+
+    /// Writes `package` and opens the sqlite collection contained in it
+    ///
+    /// The returned `TempPath` has to be kept alive as long as the connection is used.
+    pub(crate) fn write_and_open_collection(package: &mut Package) -> (Connection, TempPath) {
+        let mut out = Cursor::new(Vec::new());
+        package
+            .write_to_timestamp(&mut out, 1_600_000_000.0)
+            .unwrap();
+        let mut archive = ZipArchive::new(out).unwrap();
+        let collection_name = package.schema_version.collection_name();
+        let db_file = NamedTempFile::new().unwrap();
+        std::io::copy(
+            &mut archive.by_name(collection_name).unwrap(),
+            &mut db_file.as_file(),
+        )
+        .unwrap();
+        let db_path = db_file.into_temp_path();
+        (Connection::open(&db_path).unwrap(), db_path)
+    }
+
+    fn read_media_map(archive: &mut ZipArchive<Cursor<Vec<u8>>>) -> BTreeMap<String, String> {
+        let mut media_json = String::new();
+        archive
+            .by_name("media")
+            .unwrap()
+            .read_to_string(&mut media_json)
+            .unwrap();
+        serde_json::from_str(&media_json).unwrap()
+    }
+
+    #[test]
+    fn write_to_file_path() {
+        let out_dir = tempfile::TempDir::new().unwrap();
+        let out_file = out_dir.path().join("output.apkg");
+        Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![])
+            .unwrap()
+            .write_to_file(&out_file)
+            .unwrap();
+        assert!(out_file.is_file());
+    }
+
+    #[test]
+    fn read_written_package() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "A deck");
+        deck.add_note(
+            Note::new(&model, vec!["Hello", "[sound:hello.mp3]"])
+                .unwrap()
+                .tags(["greeting", "audio"])
+                .guid("hello-guid"),
+        );
+        deck.add_note(Note::new(&model, vec!["Goodbye", "Bye"]).unwrap());
+        let mut package = Package::new_with_bytes(vec![deck], vec![("hello.mp3", vec![1, 2, 3])]);
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+
+        out.set_position(0);
+        let imported = Package::read_from(out).unwrap();
+        assert_eq!(imported.models().len(), 1);
+        assert_eq!(imported.models()[0].id, model.id);
+        assert!(matches!(
+            imported.media_files(),
+            [MediaFile { logical_name, source: MediaSource::Bytes(data) }]
+                if logical_name == "hello.mp3" && data == &[1, 2, 3]
+        ));
+
+        let mut package = imported.to_package().unwrap();
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let notes = conn
+            .prepare("SELECT guid, tags, flds FROM notes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(
+            notes[0],
+            (
+                "hello-guid".to_string(),
+                " greeting audio ".to_string(),
+                "Hello\x1f[sound:hello.mp3]".to_string()
+            )
+        );
+        let card_decks = conn
+            .prepare("SELECT DISTINCT did FROM cards")
+            .unwrap()
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(card_decks, vec![1234]);
+    }
+
+    #[test]
+    fn schema_version_v2() {
+        let mut package = Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![])
+            .unwrap()
+            .schema_version(SchemaVersion::V2);
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let archive = ZipArchive::new(out).unwrap();
+        assert!(archive.file_names().any(|name| name == "collection.anki21"));
+        assert!(!archive.file_names().any(|name| name == "collection.anki2"));
+
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let conf_json_str: String = conn
+            .query_row("SELECT conf FROM col", [], |row| row.get(0))
+            .unwrap();
+        let conf: serde_json::Value = serde_json::from_str(&conf_json_str).unwrap();
+        assert_eq!(conf["schedVer"], 2);
+        assert_eq!(conf["curDeck"], 1);
+    }
+
+    #[test]
+    fn write_to_appends_to_vec_through_cursor() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut buffer = b"prefix".to_vec();
+        let mut cursor = Cursor::new(&mut buffer);
+        cursor.seek(SeekFrom::End(0)).unwrap();
+        Package::new_with_bytes(vec![deck], vec![("sound.mp3", vec![1, 2, 3])])
+            .write_to_timestamp(&mut cursor, 1_600_000_000.0)
+            .unwrap();
+        assert_eq!(&buffer[..6], b"prefix");
+
+        // Zip readers find the archive after the prefix, like in a self-extracting archive
+        let mut archive = ZipArchive::new(Cursor::new(buffer)).unwrap();
+        assert_eq!(
+            read_media_map(&mut archive)
+                .into_values()
+                .collect::<Vec<_>>(),
+            vec!["sound.mp3"]
+        );
+        let mut media = vec![];
+        archive
+            .by_name("0")
+            .unwrap()
+            .read_to_end(&mut media)
+            .unwrap();
+        assert_eq!(media, vec![1, 2, 3]);
+        let db_file = NamedTempFile::new().unwrap().into_temp_path();
+        std::io::copy(
+            &mut archive.by_name("collection.anki2").unwrap(),
+            &mut File::create(&db_file).unwrap(),
+        )
+        .unwrap();
+        let notes: i64 = Connection::open(&db_file)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(notes, 1);
+    }
+
+    #[test]
+    fn write_to_stream_without_seek() {
+        /// A writer which only implements `Write`, like a socket
+        struct Sink(Vec<u8>);
+        impl Write for Sink {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut sink = Sink(vec![]);
+        package
+            .write_to_stream_timestamp(&mut sink, 1_600_000_000.0)
+            .unwrap();
+        assert_eq!(
+            sink.0,
+            package.write_to_vec_timestamp(1_600_000_000.0).unwrap()
+        );
+    }
+
+    #[test]
+    fn write_spooled() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut package = Package::from_decks(vec![deck]).temp_dir(temp_dir.path());
+        let mut spooled = package.write_spooled_timestamp(1_600_000_000.0).unwrap();
+        let mut bytes = vec![];
+        spooled.read_to_end(&mut bytes).unwrap();
+        assert_eq!(
+            bytes,
+            package.write_to_vec_timestamp(1_600_000_000.0).unwrap()
+        );
+        spooled.seek(SeekFrom::Start(0)).unwrap();
+        let imported = Package::read_from(spooled).unwrap();
+        assert_eq!(imported.models().len(), 1);
+    }
+
+    #[test]
+    fn builder() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let image_path = media_dir.path().join("image.jpg");
+        std::fs::write(&image_path, [1, 2, 3]).unwrap();
+        let mut package = Package::builder()
+            .deck(Deck::new(1234, "Example Deck", ""))
+            .deck(Deck::new(5678, "Other Deck", ""))
+            .media_path(&image_path)
+            .media_bytes("sound.mp3", vec![4, 5, 6])
+            .build()
+            .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(package.write_to_vec().unwrap())).unwrap();
+        let media_map = read_media_map(&mut archive);
+        assert_eq!(media_map["0"], "image.jpg");
+        assert_eq!(media_map["1"], "sound.mp3");
+        assert_eq!(package.decks_mut().len(), 2);
+
+        assert!(matches!(
+            Package::builder().media_path("images/..").build(),
+            Err(Error::InvalidMediaPath(_))
+        ));
+        assert!(matches!(
+            Package::builder().media_bytes("a/b.jpg", vec![]).build(),
+            Err(Error::InvalidMediaPath(_))
+        ));
+    }
+
+    #[test]
+    fn progress_reported() {
+        for parallel in [false, true] {
+            let progress = std::sync::Mutex::new(vec![]);
+            let mut package = Package::new_with_bytes(
+                vec![],
+                vec![("a.jpg", vec![1]), ("b.jpg", vec![2]), ("c.jpg", vec![3])],
+            )
+            .parallel(parallel)
+            .on_progress(|stage| progress.lock().unwrap().push(stage));
+            package.write_to_vec().unwrap();
+            drop(package);
+            let media = |written| Progress::WritingMedia { written, total: 3 };
+            assert_eq!(
+                progress.into_inner().unwrap(),
+                vec![
+                    Progress::WritingCollection,
+                    media(0),
+                    media(1),
+                    media(2),
+                    media(3)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn from_decks_without_media() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package = Package::from_decks(vec![deck, Deck::new(5678, "Empty Deck", "")]);
+        assert_eq!(package.media_count(), 0);
+        assert_eq!(package.total_notes(), 1);
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let mut archive = ZipArchive::new(out).unwrap();
+        assert!(read_media_map(&mut archive).is_empty());
+    }
+
+    #[test]
+    fn total_notes() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package =
+            Package::new(vec![deck, Deck::new(5678, "Empty Deck", "")], vec![]).unwrap();
+        assert_eq!(package.total_notes(), 1);
+        package.decks_mut()[1].add_note(Note::new(&model, vec!["2", "two"]).unwrap());
+        assert_eq!(package.total_notes(), 2);
+    }
+
+    #[test]
+    fn strict_rejects_empty_package() {
+        let write_package = |deck: Deck, strict| {
+            Package::new(vec![deck], vec![])
+                .unwrap()
+                .strict(strict)
+                .write_to_vec()
+        };
+        let empty_deck = Deck::new(1234, "Example Deck", "");
+        assert!(matches!(
+            write_package(empty_deck.clone(), true),
+            Err(Error::EmptyPackage)
+        ));
+        assert!(write_package(empty_deck, false).is_ok());
+
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        assert!(write_package(deck, true).is_ok());
+    }
+
+    #[test]
+    fn notes_without_cards() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        deck.add_note(
+            Note::new(&model, vec!["", "no front"])
+                .unwrap()
+                .guid("empty"),
+        );
+        let mut package = Package::from_decks(vec![deck]);
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let notes: i64 = conn
+            .query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(notes, 1);
+        assert_eq!(package.skipped_notes(), ["empty"]);
+
+        let mut package = package.strict(true);
+        assert!(matches!(
+            package.write_to_vec(),
+            Err(Error::NoCardsGenerated { guid }) if guid == "empty"
+        ));
+    }
+
+    #[test]
+    fn model_default_deck_must_exist() {
+        let model = basic_model().default_deck(5678);
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package = Package::new(vec![deck.clone()], vec![]).unwrap();
+        assert!(matches!(
+            package.write_to_vec(),
+            Err(Error::MissingDeck(5678))
+        ));
+
+        let mut package =
+            Package::new(vec![deck, Deck::new(5678, "Other Deck", "")], vec![]).unwrap();
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let models_json_str: String = conn
+            .query_row("SELECT models FROM col", [], |row| row.get(0))
+            .unwrap();
+        let models: serde_json::Value = serde_json::from_str(&models_json_str).unwrap();
+        assert_eq!(models[model.id.to_string()]["did"], 5678);
+    }
+
+    #[test]
+    fn temp_dir() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut package = Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![])
+            .unwrap()
+            .temp_dir(temp_dir.path());
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let col_rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM col", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(col_rows, 1);
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+
+        let mut package = Package::new(vec![], vec![])
+            .unwrap()
+            .temp_dir(temp_dir.path().join("missing"));
+        assert!(matches!(package.write_to_vec(), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn front_side_is_kept_in_afmt() {
+        let model = Model::new(
+            1607392319,
+            "Simple Model",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt(r#"{{FrontSide}}<hr id="answer">{{Answer}}"#)],
+        );
+        for schema_version in [SchemaVersion::V1, SchemaVersion::V2] {
+            let mut deck = Deck::new(1234, "Example Deck", "");
+            deck.add_note(Note::new(&model, vec!["Capital of France?", "Paris"]).unwrap());
+            let mut package = Package::new(vec![deck], vec![])
+                .unwrap()
+                .schema_version(schema_version);
+            let (conn, _db_file) = write_and_open_collection(&mut package);
+            let models_json_str: String = conn
+                .query_row("SELECT models FROM col", [], |row| row.get(0))
+                .unwrap();
+            let models: serde_json::Value = serde_json::from_str(&models_json_str).unwrap();
+            assert_eq!(
+                models["1607392319"]["tmpls"][0]["afmt"],
+                r#"{{FrontSide}}<hr id="answer">{{Answer}}"#
+            );
+        }
+    }
+
+    #[test]
+    fn dual_schema() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let mut package = Package::new_with_bytes(vec![deck], vec![("a.jpg", vec![1])])
+            .dual_schema(true)
+            .collection_conf("collapseTime", 600.into());
+        let mut archive = ZipArchive::new(Cursor::new(package.write_to_vec().unwrap())).unwrap();
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec!["0", "collection.anki2", "collection.anki21", "media"]
+        );
+        assert_eq!(read_media_map(&mut archive)["0"], "a.jpg");
+        for (name, scheduler_version) in [("collection.anki2", 1), ("collection.anki21", 2)] {
+            let db_file = NamedTempFile::new().unwrap();
+            std::io::copy(&mut archive.by_name(name).unwrap(), &mut db_file.as_file()).unwrap();
+            let conn = Connection::open(db_file.path()).unwrap();
+            let (conf_json_str, notes): (String, i64) = conn
+                .query_row(
+                    "SELECT conf, (SELECT COUNT(*) FROM notes) FROM col",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            let conf: serde_json::Value = serde_json::from_str(&conf_json_str).unwrap();
+            assert_eq!(conf["schedVer"], scheduler_version);
+            assert_eq!(conf["collapseTime"], 600);
+            assert_eq!(notes, 1);
+        }
+    }
+
+    #[test]
+    fn collection_conf() {
+        let mut package = Package::new(vec![], vec![])
+            .unwrap()
+            .schema_version(SchemaVersion::V2)
+            .collection_conf("collapseTime", 600.into())
+            .collection_conf("sortType", "noteFld".into())
+            .collection_conf("sortType", "cardMod".into());
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let conf_json_str: String = conn
+            .query_row("SELECT conf FROM col", [], |row| row.get(0))
+            .unwrap();
+        let conf: serde_json::Value = serde_json::from_str(&conf_json_str).unwrap();
+        assert_eq!(conf["collapseTime"], 600);
+        assert_eq!(conf["sortType"], "cardMod");
+        assert_eq!(conf["schedVer"], 2);
+        assert_eq!(conf["curDeck"], 1);
+        assert_eq!(conf["nextPos"], 1);
+    }
+
+    #[test]
+    fn id_seed() {
+        let model = basic_model();
+        let read_ids = |package: &mut Package| {
+            let (conn, _db_file) = write_and_open_collection(package);
+            let mut statement = conn
+                .prepare("SELECT id FROM notes UNION ALL SELECT id FROM cards ORDER BY id")
+                .unwrap();
+            let ids = statement
+                .query_map([], |row| row.get::<_, i64>(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            ids
+        };
+        let package = |seed: Option<i64>| {
+            let mut deck = Deck::new(1234, "Example Deck", "");
+            deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+            deck.add_note(Note::new(&model, vec!["2", "two"]).unwrap());
+            let package = Package::from_decks(vec![deck]);
+            match seed {
+                Some(seed) => package.id_seed(seed),
+                None => package,
+            }
+        };
+        assert_eq!(
+            read_ids(&mut package(None)),
+            (1_600_000_000_000..1_600_000_000_004).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            read_ids(&mut package(Some(1_500_000_000_000))),
+            (1_500_000_000_000..1_500_000_000_004).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn creation_time_and_rollover() {
+        let read_col = |package: &mut Package| {
+            let (conn, _db_file) = write_and_open_collection(package);
+            let (crt, conf_json_str): (i64, String) = conn
+                .query_row("SELECT crt, conf FROM col", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .unwrap();
+            let conf: serde_json::Value = serde_json::from_str(&conf_json_str).unwrap();
+            (crt, conf)
+        };
+        // 2020-09-13 12:26:40 UTC is written with a creation time of 2020-09-13 04:00 UTC
+        let (crt, conf) = read_col(&mut Package::new(vec![], vec![]).unwrap());
+        assert_eq!(crt, 1_599_969_600);
+        assert!(conf.get("rollover").is_none());
+        // 06:00 in UTC+1 is 05:00 UTC
+        let mut package = Package::new(vec![], vec![])
+            .unwrap()
+            .rollover(6)
+            .creation_offset(-60);
+        assert_eq!(read_col(&mut package).0, 1_599_973_200);
+        // Before the rollover the day started on the previous day, 2020-09-12 20:00 UTC
+        let mut package = Package::new(vec![], vec![]).unwrap().rollover(20);
+        assert_eq!(read_col(&mut package).0, 1_599_940_800);
+
+        let mut package = Package::new(vec![], vec![])
+            .unwrap()
+            .creation_time(1_500_000_000)
+            .rollover(30)
+            .creation_offset(-60);
+        let (crt, conf) = read_col(&mut package);
+        assert_eq!(crt, 1_500_000_000);
+        assert_eq!(conf["rollover"], 23);
+        assert_eq!(conf["creationOffset"], -60);
+    }
+
+    #[test]
+    fn usn_and_mod_written() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Parent::Child", "");
+        deck.add_note(Note::new(&model, vec!["1", "one"]).unwrap());
+        let (conn, _db_file) = write_and_open_collection(&mut Package::from_decks(vec![deck]));
+        let (col_mod, models_json, decks_json, dconf_json): (i64, String, String, String) = conn
+            .query_row("SELECT mod, models, decks, dconf FROM col", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        assert_eq!(col_mod, 1_600_000_000_000);
+        for json in [&models_json, &decks_json, &dconf_json] {
+            let entries: BTreeMap<String, serde_json::Value> = serde_json::from_str(json).unwrap();
+            for entry in entries.values() {
+                assert_eq!(entry["usn"], -1, "{}", entry["name"]);
+            }
+        }
+        let decks: BTreeMap<String, serde_json::Value> = serde_json::from_str(&decks_json).unwrap();
+        for name in ["Parent", "Parent::Child"] {
+            let deck = decks.values().find(|deck| deck["name"] == name).unwrap();
+            assert_eq!(deck["mod"], 1_600_000_000);
+        }
+        let models: BTreeMap<String, serde_json::Value> =
+            serde_json::from_str(&models_json).unwrap();
+        assert_eq!(models[&model.id.to_string()]["mod"], 1_600_000_000);
+    }
+
+    #[test]
+    fn compression_stored() {
+        let mut package = Package::new_with_bytes(
+            vec![Deck::new(1234, "Example Deck", "")],
+            vec![("image.jpg", vec![0; 100])],
+        )
+        .compression(Compression::Stored);
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let mut archive = ZipArchive::new(out).unwrap();
+        for i in 0..archive.len() {
+            let file = archive.by_index(i).unwrap();
+            assert_eq!(file.compression(), CompressionMethod::Stored);
+        }
+        assert_eq!(archive.by_name("0").unwrap().compressed_size(), 100);
+    }
+
+    #[test]
+    fn parallel_media_matches_sequential() {
+        let write_package = |parallel| {
+            let media_files = (0..50)
+                .map(|i| (format!("{}.txt", i), vec![i as u8; 100 + i]))
+                .collect::<Vec<_>>();
+            Package::new_with_bytes(
+                vec![],
+                media_files
+                    .iter()
+                    .map(|(name, data)| (name.as_str(), data.clone()))
+                    .collect(),
+            )
+            .parallel(parallel)
+            .write_to_vec_timestamp(1_600_000_000.0)
+            .unwrap()
+        };
+        let entries = |package| {
+            let mut archive = ZipArchive::new(Cursor::new(package)).unwrap();
+            (0..archive.len())
+                .map(|i| {
+                    let mut file = archive.by_index(i).unwrap();
+                    let mut data = vec![];
+                    file.read_to_end(&mut data).unwrap();
+                    (file.name().to_string(), data)
+                })
+                .filter(|(name, _)| !name.starts_with("collection"))
+                .collect::<Vec<_>>()
+        };
+        let parallel = entries(write_package(true));
+        assert_eq!(parallel, entries(write_package(false)));
+        assert_eq!(parallel.len(), 51);
+        assert_eq!(parallel[50], ("49".to_string(), vec![49; 149]));
+    }
+
+    #[test]
+    fn verify_media() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let image_path = media_dir.path().join("image.jpg");
+        std::fs::write(&image_path, [1, 2, 3]).unwrap();
+        let missing_path = media_dir.path().join("missing.jpg");
+        let mut package = Package::new(
+            vec![],
+            vec![
+                image_path.to_str().unwrap(),
+                missing_path.to_str().unwrap(),
+                "also-missing.mp3",
+            ],
+        )
+        .unwrap();
+        package.add_media_bytes("sound.mp3", vec![1, 2, 3]);
+        let expected = vec![missing_path, PathBuf::from("also-missing.mp3")];
+        assert!(
+            matches!(package.verify_media(), Err(Error::MissingMedia(paths)) if paths == expected)
+        );
+        assert!(matches!(
+            package.write_to_vec(),
+            Err(Error::MissingMedia(_))
+        ));
+
+        let package = Package::new(vec![], vec![image_path.to_str().unwrap()]).unwrap();
+        assert!(package.verify_media().is_ok());
+    }
+
+    #[test]
+    fn media_count_and_total_size() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let image_path = media_dir.path().join("image.jpg");
+        std::fs::write(&image_path, [1; 10]).unwrap();
+        let mut package = Package::new(vec![], vec![image_path.to_str().unwrap()]).unwrap();
+        package.add_media_bytes("sound.mp3", vec![2; 5]);
+        assert_eq!(package.media_count(), 2);
+        assert_eq!(package.media_total_size().unwrap(), 15);
+
+        std::fs::remove_file(&image_path).unwrap();
+        assert!(matches!(
+            package.media_total_size(),
+            Err(Error::MediaRead { path, .. }) if path == image_path
+        ));
+    }
+
+    #[test]
+    fn media_filenames_with_special_characters() {
+        let filenames = [
+            "foo bar #1 & more.mp3",
+            r#"quote " mark.jpg"#,
+            "caf\u{e9} \u{65e5}\u{672c}.png",
+        ];
+        let model = basic_model();
+        let note = Note::new(
+            &model,
+            vec![
+                &format!("[sound:{}]", filenames[0]),
+                &format!("<img src='{}'><img src=\"{}\">", filenames[1], filenames[2]),
+            ],
+        )
+        .unwrap();
+        let references = note.media_references();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(note);
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        for filename in &filenames {
+            package.add_media_bytes(filename, filename.as_bytes().to_vec());
+        }
+        let out = package.write_to_vec().unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(out.clone())).unwrap();
+        let media_map = read_media_map(&mut archive);
+        assert_eq!(
+            media_map.values().map(String::as_str).collect::<Vec<_>>(),
+            filenames
+        );
+        assert_eq!(references, filenames);
+
+        let imported = Package::read_from(Cursor::new(out)).unwrap();
+        let imported_names = imported
+            .media_files()
+            .iter()
+            .map(|media_file| media_file.filename().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(imported_names, filenames);
+    }
+
+    #[test]
+    fn media_directory_rejected() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let dir = media_dir.path().join("images.jpg");
+        std::fs::create_dir(&dir).unwrap();
+        assert!(matches!(
+            Package::new(vec![], vec![dir.to_str().unwrap()]),
+            Err(Error::NotAFile(path)) if path == dir
+        ));
+        assert!(matches!(
+            Package::builder().media_path(&dir).build(),
+            Err(Error::NotAFile(_))
+        ));
+    }
+
+    #[test]
+    fn db_and_media_compression() {
+        let mut package = Package::new_with_bytes(vec![], vec![("image.jpg", vec![0; 100])])
+            .compression(Compression::Stored)
+            .db_compression(Compression::Deflated)
+            .media_compression(Compression::Stored);
+        let mut archive = ZipArchive::new(Cursor::new(package.write_to_vec().unwrap())).unwrap();
+        for (name, compression) in [
+            ("collection.anki2", CompressionMethod::Deflated),
+            ("media", CompressionMethod::Deflated),
+            ("0", CompressionMethod::Stored),
+        ] {
+            assert_eq!(archive.by_name(name).unwrap().compression(), compression);
+        }
+    }
+
+    #[test]
+    fn dedup_media() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let image_path = media_dir.path().join("image.jpg");
+        std::fs::write(&image_path, [1, 2, 3]).unwrap();
+        let other_path = media_dir.path().join("other").join("image.jpg");
+        std::fs::create_dir(other_path.parent().unwrap()).unwrap();
+        std::fs::write(&other_path, [1, 2, 3]).unwrap();
+        let write_package = |dedup_media: bool| {
+            let mut package = Package::new(
+                vec![],
+                vec![image_path.to_str().unwrap(), other_path.to_str().unwrap()],
+            )
+            .unwrap()
+            .dedup_media(dedup_media);
+            package.add_media_bytes("image.jpg", vec![1, 2, 3]);
+            package.add_media_bytes("copy.jpg", vec![1, 2, 3]);
+            package
+        };
+        let media_names = |package: &mut Package| {
+            let mut out = Cursor::new(Vec::new());
+            package.write_to(&mut out).unwrap();
+            let mut names = read_media_map(&mut ZipArchive::new(out).unwrap())
+                .into_values()
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(media_names(&mut write_package(false)).len(), 4);
+        assert_eq!(
+            media_names(&mut write_package(true)),
+            vec!["copy.jpg", "image.jpg"]
+        );
+        for dedup_media in [false, true] {
+            let mut package = write_package(dedup_media);
+            package.add_media_bytes("image.jpg", vec![4, 5, 6]);
+            assert!(matches!(
+                package.write_to(&mut Cursor::new(Vec::new())),
+                Err(Error::MediaNameConflict(name)) if name == "image.jpg"
+            ));
+        }
+    }
+
+    #[test]
+    fn same_timestamp_gives_identical_bytes() {
+        let model = basic_model();
+        let cloze_model = crate::cloze_model();
+        let write = || {
+            let mut deck = Deck::new(1234, "Example Deck::Child", "");
+            deck.add_note(Note::new(&model, vec!["Capital of France", "Paris"]).unwrap());
+            deck.add_note(
+                Note::new(&cloze_model, vec!["{{c2::Paris}} is in {{c1::France}}"]).unwrap(),
+            );
+            let other_deck = Deck::new(5678, "Other Deck", "");
+            let names = (0..12)
+                .map(|i| format!("{}.{}", i, ["mp3", "jpg", "png"][i % 3]))
+                .collect::<Vec<_>>();
+            let mut package = Package::new_with_bytes(
+                vec![deck, other_deck],
+                (0..12)
+                    .map(|i| (names[i].as_str(), vec![i as u8; i]))
+                    .collect(),
+            );
+            let mut out = Cursor::new(Vec::new());
+            package
+                .write_to_timestamp(&mut out, 1_600_000_000.0)
+                .unwrap();
+            out.into_inner()
+        };
+        assert!(write() == write());
+    }
+
+    #[test]
+    fn zip_entries_modified_at_timestamp() {
+        let mut package = Package::new_with_bytes(vec![], vec![("sound.mp3", vec![1])]);
+        let mut out = Cursor::new(Vec::new());
+        package
+            .write_to_timestamp(&mut out, 1_600_000_000.0)
+            .unwrap();
+        let mut archive = ZipArchive::new(out).unwrap();
+        for name in ["collection.anki2", "media", "0"] {
+            let modified = archive.by_name(name).unwrap().last_modified();
+            assert_eq!(
+                (modified.year(), modified.month(), modified.day()),
+                (2020, 9, 13)
+            );
+            assert_eq!(
+                (modified.hour(), modified.minute(), modified.second()),
+                (12, 26, 40)
+            );
+        }
+
+        let before_1980 = zip_date_time(0.0);
+        assert_eq!((before_1980.year(), before_1980.month()), (1980, 1));
+        let leap_day = zip_date_time(951_782_400.0);
+        assert_eq!(
+            (leap_day.year(), leap_day.month(), leap_day.day()),
+            (2000, 2, 29)
+        );
+    }
+
+    #[test]
+    fn legacy_media_entries() {
+        let mut package = Package::new_with_bytes(
+            vec![],
+            vec![("sound.mp3", vec![1]), ("image.jpg", vec![2, 2])],
+        );
+        package.add_media_bytes("page.html", vec![3, 3, 3]);
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let mut archive = ZipArchive::new(out).unwrap();
+
+        let media_map = read_media_map(&mut archive);
+        let expected = [("0", "sound.mp3"), ("1", "image.jpg"), ("2", "page.html")]
+            .iter()
+            .map(|&(idx, name)| (idx.to_string(), name.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(media_map, expected);
+        for idx in media_map.keys() {
+            let size = archive.by_name(idx).unwrap().size();
+            assert_eq!(size, idx.parse::<u64>().unwrap() + 1);
+        }
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, vec!["0", "1", "2", "collection.anki2", "media"]);
+    }
+
+    #[test]
+    fn media_bytes_name_with_separator() {
+        let mut package = Package::new(vec![], vec![]).unwrap();
+        package.add_media_bytes("images/image.jpg", vec![1, 2, 3]);
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::InvalidMediaPath(path)) if path == Path::new("images/image.jpg")
+        ));
+    }
+
+    #[test]
+    fn card_deck_override() {
+        let model = crate::basic_and_reversed_card_model();
+        let mut recognition = Deck::new(1234, "Recognition", "");
+        recognition.add_note(
+            Note::new(&model, vec!["hola", "hello"])
+                .unwrap()
+                .card_deck(1, 5678),
+        );
+        let production = Deck::new(5678, "Production", "");
+        let mut package = Package::new(vec![recognition, production], vec![]).unwrap();
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let card_decks = conn
+            .prepare("SELECT ord, did FROM cards ORDER BY ord")
+            .unwrap()
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(card_decks, vec![(0, 1234), (1, 5678)]);
+    }
+
+    #[test]
+    fn card_deck_override_missing_deck() {
+        let model = crate::basic_and_reversed_card_model();
+        let mut deck = Deck::new(1234, "Recognition", "");
+        deck.add_note(
+            Note::new(&model, vec!["hola", "hello"])
+                .unwrap()
+                .card_deck(1, 5678),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::MissingDeck(5678))
+        ));
+    }
+
+    #[test]
+    fn models_shared_across_decks() {
+        let model = basic_model();
+        let model_copy = model.clone();
+        let decks = (0..3)
+            .map(|i| {
+                let mut deck = Deck::new(1000 + i, &format!("Deck {}", i), "");
+                deck.add_note(Note::new(&model, vec!["a", &i.to_string()]).unwrap());
+                deck.add_note(Note::new(&model_copy, vec!["b", &i.to_string()]).unwrap());
+                deck
+            })
+            .collect::<Vec<_>>();
+        let mut package = Package::from_decks(decks.clone());
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let models_json_str: String = conn
+            .query_row("SELECT models FROM col", [], |row| row.get(0))
+            .unwrap();
+        let models: BTreeMap<i64, ModelDbEntry> = serde_json::from_str(&models_json_str).unwrap();
+        assert_eq!(models.keys().copied().collect::<Vec<_>>(), vec![model.id]);
+        assert_eq!(models[&model.id].did, 1000);
+
+        let conflicting = model.clone_with_id(model.id, "Other Basic");
+        let mut decks = decks;
+        decks[2].add_note(Note::new(&conflicting, vec!["c", "d"]).unwrap());
+        assert!(matches!(
+            Package::from_decks(decks).write_to_vec(),
+            Err(Error::ModelIdConflict(id)) if id == model.id
+        ));
+    }
+
+    #[test]
+    fn merge_packages() {
+        let model = basic_model();
+        let deck_with_note = |id: i64, name: &str, front: &str| {
+            let mut deck = Deck::new(id, name, "");
+            deck.add_note(Note::new(&model, vec![front, "back"]).unwrap());
+            deck
+        };
+        let mut package = Package::new_with_bytes(
+            vec![deck_with_note(1234, "Shared", "a")],
+            vec![("sound.mp3", vec![1])],
+        );
+        let other = Package::new_with_bytes(
+            vec![
+                deck_with_note(1234, "Shared", "b"),
+                deck_with_note(5678, "Other", "c"),
+            ],
+            vec![("sound.mp3", vec![1]), ("image.jpg", vec![2])],
+        );
+        package.merge(other).unwrap();
+        assert_eq!(
+            package
+                .decks
+                .iter()
+                .map(|deck| (deck.id(), deck.note_count()))
+                .collect::<Vec<_>>(),
+            vec![(1234, 2), (5678, 1)]
+        );
+        assert_eq!(package.media_count(), 2);
+        assert!(package.write_to_vec().is_ok());
+
+        let renamed = Package::from_decks(vec![deck_with_note(5678, "Renamed", "d")]);
+        assert!(matches!(
+            package.merge(renamed),
+            Err(Error::DuplicateId(5678))
+        ));
+        let changed_media = Package::new_with_bytes(vec![], vec![("image.jpg", vec![3])]);
+        assert!(matches!(
+            package.merge(changed_media),
+            Err(Error::MediaNameConflict(name)) if name == "image.jpg"
+        ));
+        assert_eq!(package.total_notes(), 3);
+    }
+
+    #[test]
+    fn original_deck_missing_deck() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(
+            Note::new(&model, vec!["hola", "hello"])
+                .unwrap()
+                .card_scheduling(
+                    0,
+                    crate::SchedulingState {
+                        odid: 5678,
+                        ..Default::default()
+                    },
+                ),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::MissingDeck(5678))
+        ));
+    }
+
+    #[test]
+    fn add_deck_later() {
+        let model = basic_model();
+        let mut package = Package::new(vec![], vec![]).unwrap();
+        package.add_deck(Deck::new(1234, "First Deck", ""));
+        package.add_deck(Deck::new(5678, "Second Deck", ""));
+        package.decks_mut()[1].add_note(Note::new(&model, vec!["Question", "Answer"]).unwrap());
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let deck_id: i64 = conn
+            .query_row("SELECT did FROM cards", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(deck_id, 5678);
+    }
+
+    #[test]
+    fn connection_hook_runs_before_commit() {
+        let mut package = Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![])
+            .unwrap()
+            .with_connection(|transaction| {
+                let decks: String = transaction
+                    .query_row("SELECT decks FROM col", [], |row| row.get(0))
+                    .map_err(database_error)?;
+                assert!(decks.contains("Example Deck"));
+                transaction
+                    .execute("UPDATE col SET tags = '{\"hooked\": 0}'", [])
+                    .map_err(database_error)?;
+                Ok(())
+            });
+        let (conn, _db_file) = write_and_open_collection(&mut package);
+        let tags: String = conn
+            .query_row("SELECT tags FROM col", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, r#"{"hooked": 0}"#);
+
+        let mut package = Package::new(vec![], vec![])
+            .unwrap()
+            .with_connection(|_| Err(Error::MissingDeck(42)));
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::MissingDeck(42))
+        ));
+    }
+
+    #[test]
+    fn write_to_vec_matches_write_to() {
+        let mut package = Package::new(vec![Deck::new(1234, "Example Deck", "")], vec![]).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        package
+            .write_to_timestamp(&mut out, 1_600_000_000.0)
+            .unwrap();
+        let bytes = package.write_to_vec_timestamp(1_600_000_000.0).unwrap();
+        assert!(bytes == out.into_inner());
+        assert!(ZipArchive::new(Cursor::new(package.write_to_vec().unwrap())).is_ok());
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let model = Model::new(
+            1234,
+            "Model with a deck id",
+            vec![crate::Field::new("Front")],
+            vec![crate::Template::new("Card 1").qfmt("{{Front}}")],
+        );
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["front"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::DuplicateId(1234))
+        ));
+
+        let mut package = Package::new(
+            vec![
+                Deck::new(5678, "First Deck", ""),
+                Deck::new(5678, "Second Deck", ""),
+            ],
+            vec![],
+        )
+        .unwrap();
+        assert!(matches!(
+            package.write_to(Cursor::new(Vec::new())),
+            Err(Error::DuplicateId(5678))
+        ));
+    }
+
+    #[test]
+    fn media_path_without_filename() {
+        assert!(matches!(
+            Package::new(vec![], vec!["images/.."]),
+            Err(Error::InvalidMediaPath(path)) if path == Path::new("images/..")
+        ));
+    }
+
+    #[test]
+    fn media_file_logical_name() {
+        let media_dir = tempfile::TempDir::new().unwrap();
+        let path = media_dir.path().join("abc123.mp3");
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+        let mut package = Package::builder()
+            .media(MediaFile::new("hello.mp3", MediaSource::Path(path.clone())))
+            .build()
+            .unwrap();
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(
+            read_media_map(&mut archive),
+            BTreeMap::from([("0".to_string(), "hello.mp3".to_string())])
+        );
+
+        assert!(matches!(
+            Package::new_with_media(
+                vec![],
+                vec![MediaFile::new("sounds/hello.mp3", MediaSource::Path(path))]
+            ),
+            Err(Error::InvalidMediaPath(path)) if path == Path::new("sounds/hello.mp3")
+        ));
+    }
+
+    #[test]
+    fn media_bytes() {
+        let model = basic_model();
+        let mut deck = Deck::new(1234, "Example Deck", "");
+        deck.add_note(Note::new(&model, vec!["Hello", "[sound:hello.mp3]"]).unwrap());
+        let mut package = Package::new_with_bytes(vec![deck], vec![("hello.mp3", vec![1, 2, 3])]);
+        package.add_media_bytes("world.jpg", vec![4, 5]);
+
+        let mut out = Cursor::new(Vec::new());
+        package.write_to(&mut out).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(out.into_inner())).unwrap();
+        let media_map = read_media_map(&mut archive);
+        assert_eq!(media_map.len(), 2);
+        for (idx, name) in media_map {
+            let mut data = vec![];
+            archive
+                .by_name(&idx)
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            match name.as_str() {
+                "hello.mp3" => assert_eq!(data, vec![1, 2, 3]),
+                "world.jpg" => assert_eq!(data, vec![4, 5]),
+                _ => panic!("unexpected media file {}", name),
+            }
+        }
+    }
 }
@@ -0,0 +1,199 @@
+use crate::db_entries::{DeckDbEntry, ModelDbEntry};
+use crate::error::{database_error, json_error, zip_error};
+use crate::media::{MediaFile, MediaSource};
+use crate::package::DEFAULT_DECK_ID;
+use crate::{Deck, Error, Model, Note, Package};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use tempfile::NamedTempFile;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Names of the collection inside a package, in the order they are preferred when reading
+const COLLECTION_NAMES: [&str; 2] = ["collection.anki21", "collection.anki2"];
+
+/// The contents of an existing `.apkg` file, read with [`Package::read_from_file`]
+///
+/// Since the notes of a `Package` borrow their models, the models are owned by the
+/// `ImportedPackage` and [`ImportedPackage::to_package`] creates a `Package` borrowing from it.
+///
+/// Example:
+/// ```rust,no_run
+/// use genanki_rs::{Note, Package};
+///
+/// let imported = Package::read_from_file("exported.apkg").unwrap();
+/// let mut package = imported.to_package().unwrap();
+/// package.write_to_file("output.apkg").unwrap();
+/// ```
+pub struct ImportedPackage {
+    models: Vec<Model>,
+    decks: Vec<ImportedDeck>,
+    media_files: Vec<MediaFile>,
+}
+
+struct ImportedDeck {
+    id: i64,
+    name: String,
+    description: String,
+    notes: Vec<ImportedNote>,
+}
+
+struct ImportedNote {
+    model_id: i64,
+    fields: Vec<String>,
+    tags: Vec<String>,
+    guid: String,
+}
+
+impl ImportedPackage {
+    pub(crate) fn read_from<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+        let mut archive = ZipArchive::new(reader).map_err(zip_error)?;
+        let collection_name = COLLECTION_NAMES
+            .iter()
+            .find(|&&name| archive.file_names().any(|file_name| file_name == name))
+            .ok_or_else(|| zip_error(ZipError::FileNotFound))?;
+        let db_file = NamedTempFile::new()?;
+        std::io::copy(
+            &mut archive.by_name(collection_name).map_err(zip_error)?,
+            &mut db_file.as_file(),
+        )?;
+        let db_file = db_file.into_temp_path();
+        let conn = Connection::open(&db_file).map_err(database_error)?;
+
+        let (models_json_str, decks_json_str): (String, String) = conn
+            .query_row("SELECT models, decks FROM col", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(database_error)?;
+        let models: HashMap<i64, ModelDbEntry> =
+            serde_json::from_str(&models_json_str).map_err(json_error)?;
+        let mut models = models
+            .into_values()
+            .map(Model::from_model_db_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+        models.sort_by_key(|model| model.id);
+
+        let deck_entries: HashMap<i64, DeckDbEntry> =
+            serde_json::from_str(&decks_json_str).map_err(json_error)?;
+        let mut decks = deck_entries
+            .into_values()
+            .map(|entry| ImportedDeck {
+                id: entry.id,
+                name: entry.name,
+                description: entry.desc,
+                notes: vec![],
+            })
+            .collect::<Vec<_>>();
+        decks.sort_by_key(|deck| deck.id);
+
+        // A note is put into the deck of its first card
+        let mut statement = conn
+            .prepare(
+                "SELECT mid, guid, tags, flds, \
+                 (SELECT did FROM cards WHERE cards.nid = notes.id ORDER BY ord LIMIT 1) \
+                 FROM notes ORDER BY id",
+            )
+            .map_err(database_error)?;
+        let notes = statement
+            .query_map([], |row| {
+                let tags: String = row.get(2)?;
+                let fields: String = row.get(3)?;
+                Ok((
+                    row.get::<_, Option<i64>>(4)?.unwrap_or(DEFAULT_DECK_ID),
+                    ImportedNote {
+                        model_id: row.get(0)?,
+                        guid: row.get(1)?,
+                        tags: tags.split_whitespace().map(str::to_string).collect(),
+                        fields: fields.split('\x1f').map(str::to_string).collect(),
+                    },
+                ))
+            })
+            .map_err(database_error)?;
+        for note in notes {
+            let (deck_id, note) = note.map_err(database_error)?;
+            match decks.iter_mut().find(|deck| deck.id == deck_id) {
+                Some(deck) => deck.notes.push(note),
+                None => decks.push(ImportedDeck {
+                    id: deck_id,
+                    name: deck_id.to_string(),
+                    description: "".to_string(),
+                    notes: vec![note],
+                }),
+            }
+        }
+        // Every collection contains the `Default` deck, so only keep it if it is actually used
+        decks.retain(|deck| deck.id != DEFAULT_DECK_ID || !deck.notes.is_empty());
+
+        let mut media_json = String::new();
+        archive
+            .by_name("media")
+            .map_err(zip_error)?
+            .read_to_string(&mut media_json)?;
+        let media_map: HashMap<String, String> =
+            serde_json::from_str(&media_json).map_err(json_error)?;
+        let mut media_map = media_map.into_iter().collect::<Vec<_>>();
+        media_map.sort_by_key(|(idx, _)| idx.parse::<usize>().ok());
+        let mut media_files = vec![];
+        for (idx, name) in media_map {
+            let mut data = vec![];
+            archive
+                .by_name(&idx)
+                .map_err(zip_error)?
+                .read_to_end(&mut data)?;
+            media_files.push(MediaFile::new(&name, MediaSource::Bytes(data)));
+        }
+
+        Ok(Self {
+            models,
+            decks,
+            media_files,
+        })
+    }
+
+    /// Returns the models of the imported package
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    /// Returns the media files of the imported package
+    pub fn media_files(&self) -> &[MediaFile] {
+        &self.media_files
+    }
+
+    /// Creates a `Package` with the decks, notes and media of the imported package
+    ///
+    /// The `Package` can be modified, e.g. by adding notes or media, and written again. Cards are
+    /// generated from the notes again, so review history of the imported package is not kept.
+    ///
+    /// Returns `Err` if a note does not match its model, or if the name of a media file is invalid
+    pub fn to_package(&self) -> Result<Package<'_>, Error> {
+        let models = self
+            .models
+            .iter()
+            .map(|model| (model.id, model))
+            .collect::<HashMap<_, _>>();
+        let mut decks = vec![];
+        for imported_deck in &self.decks {
+            let mut deck = Deck::new(
+                imported_deck.id,
+                &imported_deck.name,
+                &imported_deck.description,
+            );
+            for note in &imported_deck.notes {
+                let model = models
+                    .get(&note.model_id)
+                    .ok_or(Error::MissingModel(note.model_id))?;
+                deck.add_note(Note::new_with_options(
+                    model,
+                    note.fields.clone(),
+                    None,
+                    Some(note.tags.clone()),
+                    Some(&note.guid),
+                )?);
+            }
+            decks.push(deck);
+        }
+        Package::new_with_media(decks, self.media_files.clone())
+    }
+}
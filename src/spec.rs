@@ -0,0 +1,137 @@
+use crate::{Deck, Error, Model, Note};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Description of a `Deck` and its notes which can be serialized, e.g. to define decks in a config
+/// file
+///
+/// Notes refer to their model by id, so [`DeckSpec::to_deck`] is given the models to create the
+/// `Deck` with. `description` and `notes` may be left out when deserializing:
+///
+/// ```json
+/// {
+///   "id": 2059400110,
+///   "name": "Country Capitals",
+///   "description": "Deck for studying country capitals",
+///   "notes": [
+///     {
+///       "model_id": 1607392319,
+///       "fields": ["Capital of Argentina", "Buenos Aires"],
+///       "tags": ["geo"]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::{basic_model, DeckSpec, NoteSpec};
+///
+/// let models = vec![basic_model()];
+/// let spec = DeckSpec {
+///     id: 2059400110,
+///     name: "Country Capitals".to_string(),
+///     description: "".to_string(),
+///     notes: vec![NoteSpec::new(models[0].id, vec!["Capital of Argentina", "Buenos Aires"])],
+/// };
+/// let deck = spec.to_deck(&models).unwrap();
+/// assert_eq!(deck.note_count(), 1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeckSpec {
+    pub id: i64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<NoteSpec>,
+}
+
+/// Description of a `Note` which can be serialized, see [`DeckSpec`]
+///
+/// `tags`, `guid` and `sort_field` may be left out when deserializing, and the defaults of
+/// [`Note::new`] are used for them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteSpec {
+    pub model_id: i64,
+    pub fields: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_field: Option<String>,
+}
+
+impl DeckSpec {
+    /// Creates a `Deck` with the notes of this description, looking up their models in `models`
+    ///
+    /// Returns `Err` if the model of a note is not in `models`, or if a note does not match its
+    /// model
+    pub fn to_deck<'a>(&self, models: &'a [Model]) -> Result<Deck<'a>, Error> {
+        let models = models
+            .iter()
+            .map(|model| (model.id, model))
+            .collect::<HashMap<_, _>>();
+        let mut deck = Deck::new(self.id, &self.name, &self.description);
+        for note in &self.notes {
+            let model = models
+                .get(&note.model_id)
+                .ok_or(Error::MissingModel(note.model_id))?;
+            deck.add_note(Note::new_with_options(
+                model,
+                note.fields.clone(),
+                note.sort_field.as_deref(),
+                Some(note.tags.clone()),
+                note.guid.as_deref(),
+            )?);
+        }
+        Ok(deck)
+    }
+}
+
+impl NoteSpec {
+    /// Creates a description of a note of the model `model_id` with `fields`
+    pub fn new(model_id: i64, fields: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self {
+            model_id,
+            fields: fields.into_iter().map(|field| field.to_string()).collect(),
+            tags: vec![],
+            guid: None,
+            sort_field: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deck_spec_to_deck() {
+        let spec: DeckSpec = serde_json::from_str(
+            r#"{
+                "id": 2059400110,
+                "name": "Country Capitals",
+                "notes": [
+                    { "model_id": 1607392319, "fields": ["Argentina", "Buenos Aires"], "tags": ["geo"] },
+                    { "model_id": 1607392319, "fields": ["France", "Paris"], "guid": "abc" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(spec.description, "");
+        assert_eq!(spec.notes[1].guid.as_deref(), Some("abc"));
+
+        let models = vec![crate::basic_model().clone_with_id(1607392319, "Basic")];
+        let deck = spec.to_deck(&models).unwrap();
+        assert_eq!(deck.note_count(), 2);
+        assert_eq!(deck.notes()[0].fields(), ["Argentina", "Buenos Aires"]);
+        assert_eq!(deck.notes()[1].get_guid(), "abc");
+
+        assert!(matches!(
+            spec.to_deck(&[]),
+            Err(Error::MissingModel(1607392319))
+        ));
+    }
+}
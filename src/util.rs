@@ -1,15 +1,335 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use fancy_regex::{Captures, Regex};
+use rusqlite::Transaction;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::RangeFrom;
+use std::sync::OnceLock;
 
-pub fn guid_for(fields: &Vec<String>) -> String {
-    fields
+use crate::error::database_error;
+use crate::Error;
+
+/// Returns a GUID derived from `values` exactly like Python genanki's `guid_for`
+///
+/// The values are joined with `__`, the first 8 bytes of the sha256 hash of the result are
+/// encoded in base91. Notes get the GUID of their fields by default, so decks generated with
+/// genanki-rs and genanki update each other's notes on import instead of duplicating them.
+///
+/// Example:
+/// ```
+/// use genanki_rs::guid_for;
+///
+/// assert_eq!(guid_for(&["Capital of Argentina", "Buenos Aires"]), "HSnG{z%dU<");
+/// ```
+pub fn guid_for(values: &[impl Display]) -> String {
+    let joined = values
         .iter()
-        .map(|f| u64::to_string(&hash_str(&f)))
-        .collect()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("__");
+    let digest = Sha256::digest(joined.as_bytes());
+    let mut hash_bytes = [0; 8];
+    hash_bytes.copy_from_slice(&digest[..8]);
+    base91(u64::from_be_bytes(hash_bytes))
+}
+
+/// Characters of the base91 encoding Anki and genanki use for guids
+const BASE91_TABLE: &[u8; 91] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&()*+,-./:;<=>?@[]^_`{|}~";
+
+/// Encodes `value` with the base91 alphabet, most significant digit first
+pub(crate) fn base91(mut value: u64) -> String {
+    let mut digits = vec![];
+    while value > 0 {
+        digits.push(BASE91_TABLE[(value % 91) as usize]);
+        value /= 91;
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("base91 table is ASCII")
+}
+
+/// Returns a random guid like the ones Anki creates for new notes
+pub(crate) fn random_guid() -> String {
+    base91(fastrand::u64(..))
+}
+
+/// Strips HTML from `text` the way Anki does for the sort field and duplicate checks
+///
+/// This mirrors Anki's `stripHTMLMedia`: `<img>` tags are replaced by their `src` filename
+/// surrounded by spaces, comments, `<style>` and `<script>` elements and all other tags are
+/// removed, and HTML entities like `&amp;`, `&nbsp;` or `&#233;` are decoded. Sound references
+/// like `[sound:hello.mp3]` are not HTML, so they are kept as they are.
+///
+/// Example:
+/// ```
+/// use genanki_rs::strip_html_media;
+///
+/// assert_eq!(strip_html_media("<b>Caf&eacute;</b>"), "Café");
+/// assert_eq!(strip_html_media(r#"a<img src="b.jpg">"#), "a b.jpg ");
+/// ```
+pub fn strip_html_media(text: &str) -> String {
+    // Called for every note, so the regexes are only compiled once
+    static MEDIA: OnceLock<Regex> = OnceLock::new();
+    static REMOVED: OnceLock<Regex> = OnceLock::new();
+    static TAGS: OnceLock<Regex> = OnceLock::new();
+    let media = MEDIA.get_or_init(|| {
+        Regex::new(r#"(?i)<img[^>]+src=["']?([^"'>]+)["']?[^>]*>"#).expect("static regex")
+    });
+    let text = media.replace_all(text, " $1 ");
+    let removed = REMOVED.get_or_init(|| {
+        Regex::new(r"(?s)<!--.*?-->|(?si)<style.*?>.*?</style>|(?si)<script.*?>.*?</script>")
+            .expect("static regex")
+    });
+    let text = removed.replace_all(&text, "");
+    let tags = TAGS.get_or_init(|| Regex::new(r"(?s)<.*?>").expect("static regex"));
+    let text = tags.replace_all(&text, "");
+    decode_entities(&text.replace("&nbsp;", " "))
 }
 
-fn hash_str(to_hash: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    to_hash.hash(&mut s);
-    s.finish()
+/// Checksum Anki uses to find duplicate notes, see `notes.csum`
+///
+/// This is the first 8 hex digits of the sha1 hash of the field with HTML stripped, as an integer.
+pub(crate) fn field_checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(strip_html_media(field).as_bytes());
+    let mut hash_bytes = [0; 4];
+    hash_bytes.copy_from_slice(&digest[..4]);
+    u32::from_be_bytes(hash_bytes).into()
+}
+
+/// Decodes named and numeric HTML entities, leaving unknown entities unchanged
+pub(crate) fn decode_entities(text: &str) -> String {
+    static ENTITY: OnceLock<Regex> = OnceLock::new();
+    let entity = ENTITY.get_or_init(|| {
+        Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").expect("static regex")
+    });
+    entity
+        .replace_all(text, |caps: &Captures| {
+            let name = &caps[1];
+            let decoded = match name.strip_prefix('#') {
+                Some(code) => match code.strip_prefix(['x', 'X'].as_ref()) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code.parse().ok(),
+                }
+                .and_then(char::from_u32),
+                None => named_entity(name),
+            };
+            decoded.map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    let decoded = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "copy" => '©',
+        "reg" => '®',
+        "deg" => '°',
+        "middot" => '·',
+        "times" => '×',
+        "divide" => '÷',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "hellip" => '…',
+        "euro" => '€',
+        "aacute" => 'á',
+        "eacute" => 'é',
+        "iacute" => 'í',
+        "oacute" => 'ó',
+        "uacute" => 'ú',
+        "Aacute" => 'Á',
+        "Eacute" => 'É',
+        "Iacute" => 'Í',
+        "Oacute" => 'Ó',
+        "Uacute" => 'Ú',
+        "agrave" => 'à',
+        "egrave" => 'è',
+        "ugrave" => 'ù',
+        "Agrave" => 'À',
+        "Egrave" => 'È',
+        "acirc" => 'â',
+        "ecirc" => 'ê',
+        "icirc" => 'î',
+        "ocirc" => 'ô',
+        "ucirc" => 'û',
+        "auml" => 'ä',
+        "euml" => 'ë',
+        "iuml" => 'ï',
+        "ouml" => 'ö',
+        "uuml" => 'ü',
+        "Auml" => 'Ä',
+        "Ouml" => 'Ö',
+        "Uuml" => 'Ü',
+        "szlig" => 'ß',
+        "ccedil" => 'ç',
+        "Ccedil" => 'Ç',
+        "ntilde" => 'ñ',
+        "Ntilde" => 'Ñ',
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/// Generates ids for the rows of the collection which are not used in their table yet
+///
+/// The ids of a table are loaded once when it is first used and every id handed out is recorded,
+/// so generating an id does not query the database.
+pub(crate) struct IdGen {
+    next: RangeFrom<usize>,
+    used: HashMap<&'static str, HashSet<i64>>,
+}
+
+impl IdGen {
+    /// Creates a generator whose sequence starts at `first_id`
+    pub(crate) fn new(first_id: usize) -> Self {
+        Self {
+            next: first_id..,
+            used: HashMap::new(),
+        }
+    }
+
+    /// Returns the first id starting at `id` which is not used in `table` yet
+    pub(crate) fn unused_id(
+        &mut self,
+        transaction: &Transaction,
+        table: &'static str,
+        mut id: i64,
+    ) -> Result<i64, Error> {
+        let used = used_ids(&mut self.used, transaction, table)?;
+        while !used.insert(id) {
+            id += 1;
+        }
+        Ok(id)
+    }
+
+    /// Returns the next id of the sequence which is not used in `table` yet
+    pub(crate) fn next_unused_id(
+        &mut self,
+        transaction: &Transaction,
+        table: &'static str,
+    ) -> Result<i64, Error> {
+        let used = used_ids(&mut self.used, transaction, table)?;
+        loop {
+            let id = self.next.next().expect("range is unbounded") as i64;
+            if used.insert(id) {
+                return Ok(id);
+            }
+        }
+    }
+}
+
+fn used_ids<'a>(
+    used: &'a mut HashMap<&'static str, HashSet<i64>>,
+    transaction: &Transaction,
+    table: &'static str,
+) -> Result<&'a mut HashSet<i64>, Error> {
+    match used.entry(table) {
+        Entry::Occupied(entry) => Ok(entry.into_mut()),
+        Entry::Vacant(entry) => {
+            let mut statement = transaction
+                .prepare(&format!("SELECT id FROM {}", table))
+                .map_err(database_error)?;
+            let ids = statement
+                .query_map([], |row| row.get(0))
+                .map_err(database_error)?
+                .collect::<Result<HashSet<i64>, _>>()
+                .map_err(database_error)?;
+            Ok(entry.insert(ids))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_html_media_removes_tags() {
+        assert_eq!(
+            strip_html_media("<b>bold</b> and <i>italic</i>"),
+            "bold and italic"
+        );
+        assert_eq!(strip_html_media("<div>a<br/>b</div><!-- comment -->"), "ab");
+        assert_eq!(
+            strip_html_media("<style>.card { color: red; }</style><script>alert(1)</script>text"),
+            "text"
+        );
+    }
+
+    #[test]
+    fn strip_html_media_keeps_media_filenames() {
+        assert_eq!(
+            strip_html_media(r#"<img src="cat.jpg"><IMG class=x src='dog.png'/>"#),
+            " cat.jpg  dog.png "
+        );
+        assert_eq!(
+            strip_html_media("[sound:hello.mp3]<b>hello</b>"),
+            "[sound:hello.mp3]hello"
+        );
+    }
+
+    #[test]
+    fn base91_encoding() {
+        assert_eq!(base91(0), "");
+        assert_eq!(base91(1), "b");
+        assert_eq!(base91(90), "~");
+        assert_eq!(base91(91), "ba");
+        assert_eq!(base91(u64::MAX), "Rj&Z5m[>Zp");
+        let guid = random_guid();
+        assert!(guid.len() <= 10);
+        assert!(guid.bytes().all(|c| BASE91_TABLE.contains(&c)));
+    }
+
+    #[test]
+    fn guid_for_matches_genanki() {
+        assert_eq!(
+            guid_for(&["Capital of Argentina", "Buenos Aires"]),
+            "HSnG{z%dU<"
+        );
+        assert_eq!(guid_for(&["a"]), "IkF(BOZ;]l");
+        assert_eq!(guid_for(&[1, 2]), "Qlb:>1my~&");
+        assert_eq!(guid_for(&[] as &[&str]), "ME_YHw2?15");
+    }
+
+    #[test]
+    fn field_checksum_matches_anki() {
+        assert_eq!(field_checksum("test"), 2840236005);
+        assert_eq!(field_checksum("<b>test</b>"), 2840236005);
+        assert_eq!(field_checksum("Caf&eacute;"), 2103707745);
+    }
+
+    #[test]
+    fn strip_html_media_decodes_entities() {
+        assert_eq!(
+            strip_html_media("a&nbsp;b &amp; &lt;c&gt; &#233;&#xE9; &eacute;"),
+            "a b & <c> éé é"
+        );
+        assert_eq!(strip_html_media("&unknown; &#xD800;"), "&unknown; &#xD800;");
+    }
+
+    #[test]
+    fn id_gen_skips_used_ids() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE notes (id integer primary key); INSERT INTO notes VALUES (5);",
+        )
+        .unwrap();
+        let transaction = conn.transaction().unwrap();
+        let mut id_gen = IdGen::new(4);
+        assert_eq!(id_gen.next_unused_id(&transaction, "notes").unwrap(), 4);
+        assert_eq!(id_gen.next_unused_id(&transaction, "notes").unwrap(), 6);
+        assert_eq!(id_gen.unused_id(&transaction, "notes", 4).unwrap(), 7);
+        assert_eq!(id_gen.next_unused_id(&transaction, "notes").unwrap(), 8);
+    }
 }